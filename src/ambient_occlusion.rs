@@ -0,0 +1,87 @@
+//! Ambient occlusion, the fraction of the hemisphere above a point left open by the objects
+//! within a distance, for clay renders and as an ambient term of Whitted shading.
+
+use crate::bsdf::sample_cosine_hemisphere;
+use crate::color::Rgb;
+use crate::sampler::Rng;
+use crate::scene::{scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Gray of the clay render.
+const CLAY_ALBEDO: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AoSettings {
+    /// Cosine weighted rays per shaded point.
+    pub samples: u32,
+    /// Objects farther than this distance do not occlude.
+    pub max_distance: f32,
+}
+
+impl Default for AoSettings {
+    fn default() -> AoSettings {
+        AoSettings {
+            samples: 16,
+            max_distance: 5.,
+        }
+    }
+}
+
+/// Cosine weighted fraction of the hemisphere around `n` not occluded within the distance of
+/// `settings`, 1 in the open.
+pub fn ambient_occlusion(
+    scene: &Scene,
+    settings: &AoSettings,
+    p: &Vec3f32,
+    n: &Vec3f32,
+    time: f32,
+    rng: &mut Rng,
+) -> f32 {
+    let orig = p + n * 1e-3;
+    let mut open = 0;
+    for _ in 0..settings.samples {
+        let dir = sample_cosine_hemisphere(n, rng.next_f32(), rng.next_f32());
+        let mut hit = Vec3f32::new(0., 0., 0.);
+        let mut hit_n = Vec3f32::new(0., 0., 0.);
+        let mut material = Material::default();
+        stats::count(Counter::ShadowRays, 1);
+        if !scene_intersect(
+            &orig,
+            &dir,
+            time,
+            scene,
+            &mut hit,
+            &mut hit_n,
+            &mut material,
+        ) || (hit - orig).norm() > settings.max_distance
+        {
+            open += 1;
+        }
+    }
+    open as f32 / settings.samples.max(1) as f32
+}
+
+/// Clay render of the camera ray (`orig`, `dir`): every object is a uniform gray darkened by
+/// its ambient occlusion, the background is white.
+pub fn clay(
+    scene: &Scene,
+    settings: &AoSettings,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    rng: &mut Rng,
+) -> Rgb {
+    let mut hit = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+    if !scene_intersect(orig, dir, time, scene, &mut hit, &mut n, &mut material) {
+        return Rgb::new(1., 1., 1.);
+    }
+    // Seen from inside, the hemisphere is on the side of the viewer.
+    if n.dot_product(dir) > 0. {
+        n = n * -1.;
+    }
+    let ao = ambient_occlusion(scene, settings, &hit, &n, time, rng);
+    Rgb::new(1., 1., 1.) * (CLAY_ALBEDO * ao)
+}
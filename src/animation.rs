@@ -0,0 +1,145 @@
+use crate::camera::Camera;
+use crate::color::Rgb;
+use crate::scene::Scene;
+use crate::vec3::Vec3f32;
+
+/// How a keyframe is interpolated until the next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Cubic Bezier segment with automatic handles, flat at the first and last keyframes.
+    Bezier,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub frame: f32,
+    /// Scalar properties only use the first component.
+    pub value: [f32; 3],
+    pub interpolation: Interpolation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    CameraPosition,
+    SphereCenter(usize),
+    LightPosition(usize),
+    LightIntensity(usize),
+    /// Diffuse color of every sphere using the same material.
+    MaterialColor(Vec<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub target: Target,
+    /// Sorted by frame.
+    keys: Vec<Keyframe>,
+    /// Velocity of an animated position besides the animation, from the scene or the options.
+    pub base_velocity: Vec3f32,
+}
+
+impl Track {
+    pub fn new(target: Target) -> Track {
+        Track {
+            target,
+            keys: Vec::new(),
+            base_velocity: Vec3f32::new(0., 0., 0.),
+        }
+    }
+
+    pub fn insert(&mut self, key: Keyframe) {
+        let index = self
+            .keys
+            .iter()
+            .position(|k| k.frame > key.frame)
+            .unwrap_or(self.keys.len());
+        self.keys.insert(index, key);
+    }
+
+    pub fn evaluate(&self, frame: f32) -> [f32; 3] {
+        let last = self.keys.len() - 1;
+        if frame <= self.keys[0].frame {
+            return self.keys[0].value;
+        }
+        if frame >= self.keys[last].frame {
+            return self.keys[last].value;
+        }
+        let k = self.keys.iter().rposition(|k| k.frame <= frame).unwrap();
+        let (k0, k1) = (&self.keys[k], &self.keys[k + 1]);
+        let t = (frame - k0.frame) / (k1.frame - k0.frame);
+        let mut value = [0.; 3];
+        for (c, v) in value.iter_mut().enumerate() {
+            let (p0, p1) = (k0.value[c], k1.value[c]);
+            *v = match k0.interpolation {
+                Interpolation::Step => p0,
+                Interpolation::Linear => p0 + (p1 - p0) * t,
+                Interpolation::Bezier => {
+                    let c0 = p0 + self.tangent(k, c) / 3.;
+                    let c1 = p1 - self.tangent(k + 1, c) / 3.;
+                    let s = 1. - t;
+                    s * s * s * p0 + 3. * s * s * t * c0 + 3. * s * t * t * c1 + t * t * t * p1
+                }
+            };
+        }
+        value
+    }
+
+    /// Catmull-Rom tangent of key `k` for component `c`, scaled to the segment following `k`
+    /// (or preceding it for the last key).
+    fn tangent(&self, k: usize, c: usize) -> f32 {
+        if k == 0 || k + 1 == self.keys.len() {
+            return 0.;
+        }
+        let (prev, key, next) = (&self.keys[k - 1], &self.keys[k], &self.keys[k + 1]);
+        let slope = (next.value[c] - prev.value[c]) / (next.frame - prev.frame);
+        slope * (next.frame - key.frame).min(key.frame - prev.frame)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Animation {
+    pub tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn track_mut(&mut self, target: Target) -> &mut Track {
+        let index = self.tracks.iter().position(|t| t.target == target);
+        match index {
+            Some(index) => &mut self.tracks[index],
+            None => {
+                self.tracks.push(Track::new(target));
+                self.tracks.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Sets the animated properties for `frame`, the velocity toward the next frame is added to
+    /// the base velocity of positions so motion blur follows the animation. The BVH must be
+    /// rebuilt afterwards.
+    pub fn apply(&self, frame: f32, scene: &mut Scene, camera: &mut Camera) {
+        for track in &self.tracks {
+            let [x, y, z] = track.evaluate(frame);
+            let [nx, ny, nz] = track.evaluate(frame + 1.);
+            let value = Vec3f32::new(x, y, z);
+            let velocity = track.base_velocity + Vec3f32::new(nx - x, ny - y, nz - z);
+            match track.target {
+                Target::CameraPosition => {
+                    camera.position = value;
+                    camera.velocity = velocity;
+                }
+                Target::SphereCenter(i) => {
+                    scene.spheres[i].center = value;
+                    scene.spheres[i].velocity = velocity;
+                }
+                Target::LightPosition(i) => scene.lights[i].position = value,
+                Target::LightIntensity(i) => scene.lights[i].intensity = x,
+                Target::MaterialColor(ref spheres) => {
+                    for &i in spheres {
+                        scene.spheres[i].material.diffuse_color = Rgb::new(x, y, z);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Arbitrary output variables: auxiliary passes recorded at the first surface seen by the
+//! camera rays, next to the final image. Only the Whitted integrator splits its lighting into
+//! terms, the other integrators record the surface passes alone.
+
+use crate::color::{false_color, Rgb};
+use crate::scene::{scene_intersect_object, Material, Scene};
+use crate::vec3::Vec3f32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    /// Distance from the camera along the ray.
+    Depth,
+    Position,
+    /// Outward shading normal.
+    Normal,
+    Albedo,
+    Uv,
+    /// 0 for the background, 1 for the checkerboard and the index of the sphere plus 2.
+    ObjectId,
+    /// Same numbering as `ObjectId` with the index of the material of the sphere.
+    MaterialId,
+    DirectDiffuse,
+    DirectSpecular,
+    Reflection,
+    Refraction,
+    Emission,
+    /// Fraction of the point light diffuse lighting blocked by occluders.
+    Shadow,
+}
+
+pub const AOV_COUNT: usize = 13;
+
+pub const ALL_AOVS: [Aov; AOV_COUNT] = [
+    Aov::Depth,
+    Aov::Position,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::Uv,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::DirectDiffuse,
+    Aov::DirectSpecular,
+    Aov::Reflection,
+    Aov::Refraction,
+    Aov::Emission,
+    Aov::Shadow,
+];
+
+/// Values of every AOV for one camera sample, indexed by `Aov as usize`, scalars are stored
+/// in the red channel.
+pub type AovSample = [Rgb; AOV_COUNT];
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "diffuse",
+            Aov::DirectSpecular => "specular",
+            Aov::Reflection => "reflection",
+            Aov::Refraction => "refraction",
+            Aov::Emission => "emission",
+            Aov::Shadow => "shadow",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    /// Depth and IDs keep the value of the first sample of a pixel instead of an average,
+    /// which would blend unrelated objects.
+    pub fn is_filtered(self) -> bool {
+        !matches!(self, Aov::Depth | Aov::ObjectId | Aov::MaterialId)
+    }
+
+    /// EXR channels suffixes of the AOV.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId | Aov::Shadow => &["Y"],
+            Aov::Uv => &["U", "V"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Values of the AOV over the image turned into displayable colors: depths and
+    /// positions are stretched over their range, IDs get distinct colors and normals are
+    /// moved from [-1, 1] to [0, 1].
+    pub fn display(self, values: &[Rgb]) -> Vec<Rgb> {
+        match self {
+            Aov::Depth | Aov::Position => {
+                let finite = |v: &&Rgb| v.r.is_finite() && v.g.is_finite() && v.b.is_finite();
+                let (min, max) = values.iter().filter(finite).fold(
+                    (f32::INFINITY, f32::NEG_INFINITY),
+                    |(min, max), v| {
+                        (
+                            min.min(v.r.min(v.g).min(v.b)),
+                            max.max(v.r.max(v.g).max(v.b)),
+                        )
+                    },
+                );
+                let range = (max - min).max(1e-6);
+                values
+                    .iter()
+                    .map(|v| match self {
+                        Aov::Depth if !v.r.is_finite() => Rgb::new(1., 1., 1.),
+                        Aov::Depth => Rgb::new(1., 1., 1.) * ((v.r - min) / range),
+                        _ => (*v - min) / range,
+                    })
+                    .collect()
+            }
+            Aov::ObjectId | Aov::MaterialId => values
+                .iter()
+                .map(|v| match v.r as u32 {
+                    0 => Rgb::new(0., 0., 0.),
+                    // Golden ratio steps spread the IDs over the ramp.
+                    id => false_color((id as f32 * 0.618_034).fract()),
+                })
+                .collect(),
+            Aov::Normal => values.iter().map(|v| *v * 0.5 + 0.5).collect(),
+            Aov::Shadow => values.iter().map(|v| Rgb::new(v.r, v.r, v.r)).collect(),
+            _ => values.to_vec(),
+        }
+    }
+}
+
+/// Values of the AOVs at a surface hit by a camera ray, before the lighting is known.
+#[allow(clippy::too_many_arguments)]
+pub fn record_surface(
+    aovs: &mut AovSample,
+    depth: f32,
+    point: &Vec3f32,
+    n: &Vec3f32,
+    albedo: &Rgb,
+    uv: (f32, f32),
+    object_id: usize,
+    material_id: usize,
+) {
+    aovs[Aov::Depth as usize] = Rgb::new(depth, depth, depth);
+    aovs[Aov::Position as usize] = Rgb::new(point.x, point.y, point.z);
+    aovs[Aov::Normal as usize] = Rgb::new(n.x, n.y, n.z);
+    aovs[Aov::Albedo as usize] = *albedo;
+    aovs[Aov::Uv as usize] = Rgb::new(uv.0, uv.1, 0.);
+    aovs[Aov::ObjectId as usize] = Rgb::new(object_id as f32, 0., 0.);
+    aovs[Aov::MaterialId as usize] = Rgb::new(material_id as f32, 0., 0.);
+}
+
+/// Records the surface passes of the first object hit by the camera ray (`orig`, `dir`).
+pub fn record_primary(
+    aovs: &mut AovSample,
+    scene: &Scene,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+) {
+    let mut point = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+    if let Some(object) =
+        scene_intersect_object(orig, dir, time, scene, &mut point, &mut n, &mut material)
+    {
+        let (object_id, material_id) = object.ids(scene);
+        record_surface(
+            aovs,
+            (point - orig).norm(),
+            &point,
+            &n,
+            &material.diffuse_color,
+            object.uv(&point, &n),
+            object_id,
+            material_id,
+        );
+    }
+}
+
+/// Terms of Whitted shading at a surface, their sum is the color of the surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Shading {
+    pub diffuse: Rgb,
+    pub specular: Rgb,
+    pub reflection: Rgb,
+    pub refraction: Rgb,
+    pub emission: Rgb,
+    pub shadow: f32,
+}
+
+impl Shading {
+    pub fn total(&self) -> Rgb {
+        self.diffuse + self.specular + self.reflection + self.refraction + self.emission
+    }
+
+    /// Records the terms seen through `transmittance`.
+    pub fn record(&self, aovs: &mut AovSample, transmittance: &Rgb) {
+        aovs[Aov::DirectDiffuse as usize] = self.diffuse * transmittance;
+        aovs[Aov::DirectSpecular as usize] = self.specular * transmittance;
+        aovs[Aov::Reflection as usize] = self.reflection * transmittance;
+        aovs[Aov::Refraction as usize] = self.refraction * transmittance;
+        aovs[Aov::Emission as usize] = self.emission * transmittance;
+        aovs[Aov::Shadow as usize] = Rgb::new(self.shadow, 0., 0.);
+    }
+}
+
+/// Empty AOVs of a camera sample missing every object.
+pub fn background() -> AovSample {
+    let mut aovs = [Rgb::new(0., 0., 0.); AOV_COUNT];
+    aovs[Aov::Depth as usize] = Rgb::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    aovs
+}
@@ -0,0 +1,542 @@
+//! Bidirectional path tracing with multiple importance sampling, following the formulation
+//! of Veach's thesis and pbrt-v3: http://www.pbr-book.org/3ed-2018/Light_Transport_III_Bidirectional_Methods/Bidirectional_Path_Tracing.html
+
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::bsdf::{sample_uniform_sphere, Bsdf};
+use crate::camera::Camera;
+use crate::color::Rgb;
+use crate::sampler::Rng;
+use crate::scene::{offset_origin, scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Light reaching the camera from light subpaths, written from every thread. The light is
+/// summed in fixed point, so that the result does not depend on the order of the splats.
+pub struct SplatBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<[AtomicI64; 3]>,
+}
+
+/// Fixed point units per unit of light.
+const SPLAT_SCALE: f64 = (1u64 << 32) as f64;
+
+impl SplatBuffer {
+    pub fn new(width: usize, height: usize) -> SplatBuffer {
+        SplatBuffer::from_raw(width, height, &vec![[0; 3]; width * height])
+    }
+
+    fn add(&self, x: f32, y: f32, value: Rgb) {
+        let (x, y) = (
+            (x as usize).min(self.width - 1),
+            (y as usize).min(self.height - 1),
+        );
+        let pixel = &self.pixels[y * self.width + x];
+        for (sum, c) in pixel.iter().zip(&[value.r, value.g, value.b]) {
+            sum.fetch_add((*c as f64 * SPLAT_SCALE).round() as i64, Ordering::Relaxed);
+        }
+    }
+
+    /// Fixed point sums of the light splatted so far.
+    pub fn raw(&self) -> Vec<[i64; 3]> {
+        self.pixels
+            .iter()
+            .map(|p| {
+                let load = |k: usize| p[k].load(Ordering::Relaxed);
+                [load(0), load(1), load(2)]
+            })
+            .collect()
+    }
+
+    /// Sums of the pixels which received light since the last call, which are cleared.
+    pub fn take_sparse(&self) -> Vec<(u32, [i64; 3])> {
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter_map(|(index, p)| {
+                let take = |k: usize| p[k].swap(0, Ordering::Relaxed);
+                let splat = [take(0), take(1), take(2)];
+                if splat == [0; 3] {
+                    None
+                } else {
+                    Some((index as u32, splat))
+                }
+            })
+            .collect()
+    }
+
+    /// Buffer holding the `raw` sums of another one.
+    pub fn from_raw(width: usize, height: usize, raw: &[[i64; 3]]) -> SplatBuffer {
+        SplatBuffer {
+            width,
+            height,
+            pixels: raw
+                .iter()
+                .map(|v| {
+                    [
+                        AtomicI64::new(v[0]),
+                        AtomicI64::new(v[1]),
+                        AtomicI64::new(v[2]),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds the splatted light to `framebuffer`, `light_paths` is the number of light subpaths
+    /// traced for the whole image.
+    pub fn resolve(&self, framebuffer: &mut [Rgb], light_paths: u64) {
+        let scale = (self.width * self.height) as f64 / light_paths.max(1) as f64 / SPLAT_SCALE;
+        for (v, splat) in framebuffer.iter_mut().zip(self.raw()) {
+            let c = |k: usize| (splat[k] as f64 * scale) as f32;
+            *v = *v + Rgb::new(c(0), c(1), c(2));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    p: Vec3f32,
+    /// Surface normal, zero for the endpoints.
+    n: Vec3f32,
+    /// Direction toward the previous vertex of the subpath.
+    wo: Vec3f32,
+    bsdf: Option<Bsdf>,
+    beta: Rgb,
+    /// Area densities of sampling this vertex from the previous one and the other way around.
+    pdf_fwd: f32,
+    pdf_rev: f32,
+    delta: bool,
+}
+
+struct Context<'a> {
+    scene: &'a Scene,
+    camera: &'a Camera,
+    width: usize,
+    height: usize,
+    time: f32,
+    light_tracing: bool,
+}
+
+fn normalized(v: Vec3f32) -> Vec3f32 {
+    let mut v = v;
+    v.normalize();
+    v
+}
+
+fn is_black(c: &Rgb) -> bool {
+    c.r <= 0. && c.g <= 0. && c.b <= 0.
+}
+
+impl Vertex {
+    fn endpoint(kind: VertexKind, p: Vec3f32, beta: Rgb, pdf_fwd: f32) -> Vertex {
+        let zero = Vec3f32::new(0., 0., 0.);
+        Vertex {
+            kind,
+            p,
+            n: zero,
+            wo: zero,
+            bsdf: None,
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    fn connectible(&self) -> bool {
+        match self.bsdf {
+            Some(ref bsdf) => bsdf.has_smooth_lobes(),
+            None => true,
+        }
+    }
+
+    fn f(&self, next: &Vertex) -> Rgb {
+        match self.bsdf {
+            Some(ref bsdf) => bsdf.f(&self.wo, &normalized(next.p - self.p)),
+            None => Rgb::new(0., 0., 0.),
+        }
+    }
+
+    /// Turns the solid angle density `pdf` of sampling `next` into an area density.
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let w = next.p - self.p;
+        let dist2 = w.dot_product(&w);
+        if dist2 == 0. {
+            return 0.;
+        }
+        let mut pdf = pdf / dist2;
+        if next.kind == VertexKind::Surface {
+            pdf *= next.n.dot_product(&w).abs() / dist2.sqrt();
+        }
+        pdf
+    }
+
+    /// Area density of sampling `next` from this vertex, reached from `prev`.
+    fn pdf(&self, ctx: &Context, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        let dir = normalized(next.p - self.p);
+        let pdf = match self.kind {
+            VertexKind::Light => 1. / (4. * PI),
+            VertexKind::Camera => ctx.camera.importance(ctx.width, ctx.height, &dir).1,
+            VertexKind::Surface => match (self.bsdf, prev) {
+                (Some(bsdf), Some(prev)) => bsdf.pdf(&normalized(prev.p - self.p), &dir),
+                _ => 0.,
+            },
+        };
+        self.convert_density(pdf, next)
+    }
+}
+
+fn intersect(ctx: &Context, orig: &Vec3f32, dir: &Vec3f32) -> Option<(Vec3f32, Vec3f32, Material)> {
+    let mut hit = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+    if scene_intersect(
+        orig,
+        dir,
+        ctx.time,
+        ctx.scene,
+        &mut hit,
+        &mut n,
+        &mut material,
+    ) {
+        Some((hit, n, material))
+    } else {
+        None
+    }
+}
+
+fn visible(ctx: &Context, a: &Vertex, b: &Vertex) -> bool {
+    stats::count(Counter::ShadowRays, 1);
+    let dir = normalized(b.p - a.p);
+    let orig = if a.kind == VertexKind::Surface {
+        offset_origin(&a.p, &a.n, &dir)
+    } else {
+        a.p
+    };
+    let target = if b.kind == VertexKind::Surface {
+        offset_origin(&b.p, &b.n, &(dir * -1.))
+    } else {
+        b.p
+    };
+    let dist = (target - orig).norm();
+    match intersect(ctx, &orig, &dir) {
+        Some((hit, _, _)) => (hit - orig).norm() >= dist,
+        None => true,
+    }
+}
+
+/// Extends `path` up to `max_vertices` vertices by sampling the BSDFs, returns the radiance
+/// of the emissive surfaces hit and of the env map when the path escapes the scene. `ray`
+/// leaves the last vertex of `path`.
+fn random_walk(
+    ctx: &Context,
+    rng: &mut Rng,
+    ray: (Vec3f32, Vec3f32),
+    beta: Rgb,
+    pdf_dir: f32,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+) -> Rgb {
+    let ((mut orig, mut dir), mut beta, mut pdf_fwd) = (ray, beta, pdf_dir);
+    let mut emitted = Rgb::new(0., 0., 0.);
+    while path.len() < max_vertices {
+        let (hit, n, material) = match intersect(ctx, &orig, &dir) {
+            Some(hit) => hit,
+            None => return emitted + beta * ctx.scene.environment(&dir),
+        };
+        emitted = emitted + beta * material.emitted(ctx.scene, &n, &dir);
+        let bsdf = Bsdf::new(&material, n);
+        let prev = path.len() - 1;
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface,
+            p: hit,
+            n,
+            wo: dir * -1.,
+            bsdf: Some(bsdf),
+            beta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta: false,
+        };
+        vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let sample = match bsdf.sample(&vertex.wo, [rng.next_f32(), rng.next_f32(), rng.next_f32()])
+        {
+            Some(sample) => sample,
+            None => break,
+        };
+        beta = beta * sample.f * (sample.wi.dot_product(&n).abs() / sample.pdf);
+        if is_black(&beta) {
+            break;
+        }
+        let pdf_rev = if sample.delta {
+            pdf_fwd = 0.;
+            0.
+        } else {
+            pdf_fwd = sample.pdf;
+            bsdf.pdf(&sample.wi, &vertex.wo)
+        };
+        let current = path.len() - 1;
+        path[current].delta = sample.delta;
+        path[prev].pdf_rev = path[current].convert_density(pdf_rev, &path[prev]);
+        orig = offset_origin(&hit, &n, &sample.wi);
+        dir = sample.wi;
+    }
+    emitted
+}
+
+fn remap0(pdf: f32) -> f32 {
+    if pdf != 0. {
+        pdf
+    } else {
+        1.
+    }
+}
+
+/// Power heuristic weight of the strategy using `s` light and `t` camera vertices, `sampled`
+/// replaces the endpoint sampled during the connection when `s` or `t` is 1.
+fn mis_weight(
+    ctx: &Context,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f32 {
+    if s + t == 2 {
+        return 1.;
+    }
+    let mut lv: Vec<Vertex> = light_path[..s].to_vec();
+    let mut cv: Vec<Vertex> = camera_path[..t].to_vec();
+    if let Some(sampled) = sampled {
+        if s == 1 {
+            lv[0] = *sampled;
+        } else if t == 1 {
+            cv[0] = *sampled;
+        }
+    }
+
+    // Densities of the reverse direction around the connection.
+    let (qs, pt) = (lv[s - 1], cv[t - 1]);
+    let qs_minus = if s > 1 { Some(lv[s - 2]) } else { None };
+    let pt_minus = if t > 1 { Some(cv[t - 2]) } else { None };
+    cv[t - 1].pdf_rev = qs.pdf(ctx, qs_minus.as_ref(), &pt);
+    if let Some(ref pt_minus) = pt_minus {
+        cv[t - 2].pdf_rev = pt.pdf(ctx, Some(&qs), pt_minus);
+    }
+    lv[s - 1].pdf_rev = pt.pdf(ctx, pt_minus.as_ref(), &qs);
+    if let Some(ref qs_minus) = qs_minus {
+        lv[s - 2].pdf_rev = qs.pdf(ctx, Some(&pt), qs_minus);
+    }
+    cv[t - 1].delta = false;
+    lv[s - 1].delta = false;
+
+    let mut sum_ri = 0.;
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        ri *= remap0(cv[i].pdf_rev) / remap0(cv[i].pdf_fwd);
+        let possible = i > 1 || ctx.light_tracing;
+        if possible && !cv[i].delta && !cv[i - 1].delta {
+            sum_ri += ri * ri;
+        }
+    }
+    ri = 1.;
+    for i in (0..s).rev() {
+        ri *= remap0(lv[i].pdf_rev) / remap0(lv[i].pdf_fwd);
+        // Point lights can not be hit by camera subpaths.
+        let delta_light = if i > 0 { lv[i - 1].delta } else { true };
+        if !lv[i].delta && !delta_light {
+            sum_ri += ri * ri;
+        }
+    }
+    1. / (1. + sum_ri)
+}
+
+/// Radiance along the camera ray (`orig`, `dir`), light subpaths reaching the camera directly
+/// are added to `splats`.
+#[allow(clippy::too_many_arguments)]
+pub fn sample(
+    scene: &Scene,
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    rng: &mut Rng,
+    max_depth: usize,
+    splats: &SplatBuffer,
+) -> Rgb {
+    let ctx = Context {
+        scene,
+        camera,
+        width,
+        height,
+        time,
+        light_tracing: camera.supports_light_tracing(),
+    };
+    let white = Rgb::new(1., 1., 1.);
+
+    let mut camera_path = Vec::with_capacity(max_depth + 2);
+    camera_path.push(Vertex::endpoint(VertexKind::Camera, *orig, white, 1.));
+    let pdf_dir = if ctx.light_tracing {
+        camera.importance(width, height, dir).1
+    } else {
+        1.
+    };
+    // Point lights can not be hit, the env map and the emissive surfaces are only found by
+    // camera subpaths, the only strategy reaching them needs no weight.
+    let mut l = random_walk(
+        &ctx,
+        rng,
+        (*orig, *dir),
+        white,
+        pdf_dir,
+        max_depth + 2,
+        &mut camera_path,
+    );
+    stats::record_depth(camera_path.len() - 1);
+
+    let mut light_path = Vec::with_capacity(max_depth + 1);
+    if !scene.lights.is_empty() {
+        let light_pdf = 1. / scene.lights.len() as f32;
+        let light = &scene.lights
+            [((rng.next_f32() * scene.lights.len() as f32) as usize).min(scene.lights.len() - 1)];
+        let intensity = light.radiant_intensity();
+        let light_dir = sample_uniform_sphere(rng.next_f32(), rng.next_f32());
+        let pdf_dir = 1. / (4. * PI);
+        light_path.push(Vertex::endpoint(
+            VertexKind::Light,
+            light.position,
+            white * intensity,
+            light_pdf,
+        ));
+        random_walk(
+            &ctx,
+            rng,
+            (light.position, light_dir),
+            white * (intensity / (light_pdf * pdf_dir)),
+            pdf_dir,
+            max_depth + 1,
+            &mut light_path,
+        );
+    }
+
+    for t in 1..=camera_path.len() {
+        for s in 1..=light_path.len() {
+            if (s == 1 && t == 1) || s + t - 2 > max_depth {
+                continue;
+            }
+            if t == 1 {
+                if ctx.light_tracing {
+                    connect_to_camera(&ctx, &light_path, &camera_path, s, splats);
+                }
+            } else if s == 1 {
+                l = l + connect_to_light(&ctx, rng, &light_path, &camera_path, t);
+            } else {
+                let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+                if !qs.connectible() || !pt.connectible() {
+                    continue;
+                }
+                let mut contribution = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+                if is_black(&contribution) {
+                    continue;
+                }
+                let d = pt.p - qs.p;
+                let dist2 = d.dot_product(&d);
+                let g = (qs.n.dot_product(&d) * pt.n.dot_product(&d)).abs() / (dist2 * dist2);
+                if g == 0. || !visible(&ctx, qs, pt) {
+                    continue;
+                }
+                contribution = contribution * g;
+                l = l + contribution * mis_weight(&ctx, &light_path, &camera_path, None, s, t);
+            }
+        }
+    }
+    l
+}
+
+/// Strategy s = 1: next event estimation toward a point light.
+fn connect_to_light(
+    ctx: &Context,
+    rng: &mut Rng,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    t: usize,
+) -> Rgb {
+    let black = Rgb::new(0., 0., 0.);
+    let pt = &camera_path[t - 1];
+    if !pt.connectible() {
+        return black;
+    }
+    let lights = &ctx.scene.lights;
+    let light_pdf = 1. / lights.len() as f32;
+    let light = &lights[((rng.next_f32() * lights.len() as f32) as usize).min(lights.len() - 1)];
+    let to_light = light.position - pt.p;
+    let dist2 = to_light.dot_product(&to_light);
+    let wi = normalized(to_light);
+    let li = light.radiant_intensity() / dist2;
+    let sampled = Vertex::endpoint(
+        VertexKind::Light,
+        light.position,
+        Rgb::new(1., 1., 1.) * (li / light_pdf),
+        light_pdf,
+    );
+    let contribution = pt.beta * pt.f(&sampled) * sampled.beta * wi.dot_product(&pt.n).abs();
+    if is_black(&contribution) || !visible(ctx, pt, &sampled) {
+        return black;
+    }
+    contribution * mis_weight(ctx, light_path, camera_path, Some(&sampled), 1, t)
+}
+
+/// Strategy t = 1: the light subpath vertex is projected on the image.
+fn connect_to_camera(
+    ctx: &Context,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    splats: &SplatBuffer,
+) {
+    let qs = &light_path[s - 1];
+    if !qs.connectible() {
+        return;
+    }
+    let position = ctx.camera.position_at(ctx.time);
+    let to_camera = position - qs.p;
+    let dist2 = to_camera.dot_product(&to_camera);
+    let wi = normalized(to_camera);
+    let dir = wi * -1.;
+    let (x, y) = match ctx.camera.raster_position(ctx.width, ctx.height, &dir) {
+        Some(raster) => raster,
+        None => return,
+    };
+    let (importance, _) = ctx.camera.importance(ctx.width, ctx.height, &dir);
+    let pdf = dist2 / -dir.z;
+    let sampled = Vertex::endpoint(
+        VertexKind::Camera,
+        position,
+        Rgb::new(1., 1., 1.) * (importance / pdf),
+        0.,
+    );
+    let contribution = qs.beta * qs.f(&sampled) * sampled.beta * wi.dot_product(&qs.n).abs();
+    if is_black(&contribution) || !visible(ctx, qs, &sampled) {
+        return;
+    }
+    let weight = mis_weight(ctx, light_path, camera_path, Some(&sampled), s, 1);
+    splats.add(x, y, contribution * weight);
+}
@@ -0,0 +1,234 @@
+use std::f32::consts::PI;
+
+use crate::color::Rgb;
+use crate::scene::Material;
+use crate::vec3::Vec3f32;
+
+/// Physically based reading of a Whitted `Material`: the albedo weights a Lambertian lobe,
+/// a normalized Phong lobe, a perfect mirror and a perfect refraction. The weights are scaled
+/// down when they add up to more than one so the surface never creates energy.
+#[derive(Debug, Clone, Copy)]
+pub struct Bsdf {
+    /// Geometric normal, the side of the surface is given by the outgoing direction.
+    n: Vec3f32,
+    diffuse: Rgb,
+    glossy: f32,
+    exponent: f32,
+    reflect: f32,
+    transmit: f32,
+    refractive_index: f32,
+    /// Probabilities to sample each lobe, in the order above.
+    lobe_pdf: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vec3f32,
+    pub f: Rgb,
+    /// Solid angle density, or lobe probability for a delta lobe.
+    pub pdf: f32,
+    pub delta: bool,
+}
+
+/// Unit vector `u` and `v` such that (`u`, `v`, `n`) is an orthonormal basis.
+pub fn orthonormal_basis(n: &Vec3f32) -> (Vec3f32, Vec3f32) {
+    // https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+    let sign = 1f32.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vec3f32::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vec3f32::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Cosine weighted direction around `n`.
+pub fn sample_cosine_hemisphere(n: &Vec3f32, u: f32, v: f32) -> Vec3f32 {
+    let r = u.sqrt();
+    let phi = 2. * PI * v;
+    let (t, b) = orthonormal_basis(n);
+    t * (r * phi.cos()) + b * (r * phi.sin()) + n * (1. - u).max(0.).sqrt()
+}
+
+/// Uniform direction in the cone around the unit vector `axis` of half angle cosine `cos_max`,
+/// with density 1 / (2 pi (1 - cos_max)).
+pub fn sample_uniform_cone(axis: &Vec3f32, cos_max: f32, u: f32, v: f32) -> Vec3f32 {
+    let cos_theta = 1. - u * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    let (t, b) = orthonormal_basis(axis);
+    t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + axis * cos_theta
+}
+
+/// Uniform direction on the unit sphere, with density 1 / (4 pi).
+pub fn sample_uniform_sphere(u: f32, v: f32) -> Vec3f32 {
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vec3f32::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+fn reflect(wo: &Vec3f32, n: &Vec3f32) -> Vec3f32 {
+    n * (2. * wo.dot_product(n)) - wo
+}
+
+/// Refraction of the outgoing direction `wo`, `None` on total internal reflection.
+fn refract(wo: &Vec3f32, n: &Vec3f32, refractive_index: f32) -> Option<Vec3f32> {
+    let mut cos_o = wo.dot_product(n);
+    let (mut n, mut eta) = (*n, 1. / refractive_index);
+    if cos_o < 0. {
+        n = n * -1.;
+        cos_o = -cos_o;
+        eta = refractive_index;
+    }
+    let k = 1. - eta * eta * (1. - cos_o * cos_o);
+    if k < 0. {
+        return None;
+    }
+    let mut wi = wo * -eta + n * (eta * cos_o - k.sqrt());
+    wi.normalize();
+    Some(wi)
+}
+
+impl Bsdf {
+    pub fn new(material: &Material, n: Vec3f32) -> Bsdf {
+        let albedo = material.albedo;
+        let (kd, ks, kr, kt) = (
+            albedo.r.max(0.),
+            albedo.g.max(0.),
+            albedo.b.max(0.),
+            albedo.a.max(0.),
+        );
+        let sum = kd + ks + kr + kt;
+        let scale = if sum > 1. { 1. / sum } else { 1. };
+        let lobe_pdf = if sum > 0. {
+            [kd / sum, ks / sum, kr / sum, kt / sum]
+        } else {
+            [0.; 4]
+        };
+        Bsdf {
+            n,
+            diffuse: material.diffuse_color * (kd * scale),
+            glossy: ks * scale,
+            exponent: material.specular_exponent,
+            reflect: kr * scale,
+            transmit: kt * scale,
+            refractive_index: material.refractive_index,
+            lobe_pdf,
+        }
+    }
+
+    /// True when the surface has a lobe that can be evaluated for any pair of directions.
+    pub fn has_smooth_lobes(&self) -> bool {
+        self.lobe_pdf[0] > 0. || self.lobe_pdf[1] > 0.
+    }
+
+    /// Value for the non delta lobes, `wo` and `wi` point away from the surface.
+    pub fn f(&self, wo: &Vec3f32, wi: &Vec3f32) -> Rgb {
+        let (cos_o, cos_i) = (wo.dot_product(&self.n), wi.dot_product(&self.n));
+        if cos_o * cos_i <= 0. {
+            return Rgb::new(0., 0., 0.);
+        }
+        let mut f = self.diffuse / PI;
+        if self.glossy > 0. {
+            let cos_alpha = reflect(wo, &self.n).dot_product(wi);
+            if cos_alpha > 0. {
+                let phong = (self.exponent + 2.) / (2. * PI) * cos_alpha.powf(self.exponent);
+                f = f + self.glossy * phong;
+            }
+        }
+        f
+    }
+
+    /// Lambertian part of `f`, what density estimation can reconstruct without noise.
+    pub fn f_diffuse(&self, wo: &Vec3f32, wi: &Vec3f32) -> Rgb {
+        if wo.dot_product(&self.n) * wi.dot_product(&self.n) <= 0. {
+            return Rgb::new(0., 0., 0.);
+        }
+        self.diffuse / PI
+    }
+
+    /// Solid angle density of sampling `wi` from `wo` with the non delta lobes.
+    pub fn pdf(&self, wo: &Vec3f32, wi: &Vec3f32) -> f32 {
+        let (cos_o, cos_i) = (wo.dot_product(&self.n), wi.dot_product(&self.n));
+        if cos_o * cos_i <= 0. {
+            return 0.;
+        }
+        let mut pdf = self.lobe_pdf[0] * cos_i.abs() / PI;
+        if self.lobe_pdf[1] > 0. {
+            let cos_alpha = reflect(wo, &self.n).dot_product(wi);
+            if cos_alpha > 0. {
+                pdf += self.lobe_pdf[1] * (self.exponent + 1.) / (2. * PI)
+                    * cos_alpha.powf(self.exponent);
+            }
+        }
+        pdf
+    }
+
+    /// Directions and weights of the mirror and refraction lobes, for integrators following
+    /// both instead of picking one.
+    pub fn delta_lobes(&self, wo: &Vec3f32) -> [Option<(Vec3f32, f32)>; 2] {
+        let mirror = reflect(wo, &self.n);
+        let reflected = if self.reflect > 0. {
+            Some((mirror, self.reflect))
+        } else {
+            None
+        };
+        let transmitted = if self.transmit > 0. {
+            let wi = refract(wo, &self.n, self.refractive_index).unwrap_or(mirror);
+            Some((wi, self.transmit))
+        } else {
+            None
+        };
+        [reflected, transmitted]
+    }
+
+    pub fn sample(&self, wo: &Vec3f32, u: [f32; 3]) -> Option<BsdfSample> {
+        let side = if wo.dot_product(&self.n) < 0. {
+            self.n * -1.
+        } else {
+            self.n
+        };
+        let [p_diffuse, p_glossy, p_reflect, _] = self.lobe_pdf;
+        let choice = u[0];
+        let wi = if choice < p_diffuse {
+            sample_cosine_hemisphere(&side, u[1], u[2])
+        } else if choice < p_diffuse + p_glossy {
+            let r = reflect(wo, &self.n);
+            let cos_alpha = u[1].powf(1. / (self.exponent + 1.));
+            let sin_alpha = (1. - cos_alpha * cos_alpha).max(0.).sqrt();
+            let phi = 2. * PI * u[2];
+            let (t, b) = orthonormal_basis(&r);
+            t * (sin_alpha * phi.cos()) + b * (sin_alpha * phi.sin()) + r * cos_alpha
+        } else {
+            let mirror = reflect(wo, &self.n);
+            let (wi, weight, pdf) = if choice < p_diffuse + p_glossy + p_reflect {
+                (mirror, self.reflect, p_reflect)
+            } else {
+                // Total internal reflection sends the refracted energy back.
+                let wi = refract(wo, &self.n, self.refractive_index).unwrap_or(mirror);
+                (wi, self.transmit, self.lobe_pdf[3])
+            };
+            let cos_i = wi.dot_product(&self.n).abs();
+            if pdf <= 0. || cos_i <= 0. {
+                return None;
+            }
+            return Some(BsdfSample {
+                wi,
+                f: Rgb::new(1., 1., 1.) * (weight / cos_i),
+                pdf,
+                delta: true,
+            });
+        };
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.f(wo, &wi),
+            pdf,
+            delta: false,
+        })
+    }
+}
@@ -0,0 +1,169 @@
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3f32,
+    pub max: Vec3f32,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3f32, max: Vec3f32) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3f32::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3f32::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    fn centroid(&self) -> Vec3f32 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Parametric range of the ray (`orig`, `dir`) inside the box, clipped to [0, `t_max`].
+    pub fn clip(&self, orig: &Vec3f32, dir: &Vec3f32, t_max: f32) -> Option<(f32, f32)> {
+        let inv_dir = Vec3f32::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let t0 = (self.min - orig) * inv_dir;
+        let t1 = (self.max - orig) * inv_dir;
+        let t_near =
+            t0.x.min(t1.x)
+                .max(t0.y.min(t1.y))
+                .max(t0.z.min(t1.z))
+                .max(0.);
+        let t_far =
+            t0.x.max(t1.x)
+                .min(t0.y.max(t1.y))
+                .min(t0.z.max(t1.z))
+                .min(t_max);
+        if t_near < t_far {
+            Some((t_near, t_far))
+        } else {
+            None
+        }
+    }
+
+    /// Slab test, `inv_dir` is the component wise inverse of the ray direction.
+    #[inline(always)]
+    fn hit(&self, orig: &Vec3f32, inv_dir: &Vec3f32, t_max: f32) -> bool {
+        let t0 = (self.min - orig) * inv_dir;
+        let t1 = (self.max - orig) * inv_dir;
+        let t_near = t0.x.min(t1.x).max(t0.y.min(t1.y)).max(t0.z.min(t1.z));
+        let t_far = t0.x.max(t1.x).min(t0.y.max(t1.y)).min(t0.z.max(t1.z));
+        t_near <= t_far && t_far >= 0. && t_near <= t_max
+    }
+}
+
+#[derive(Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    /// Index of the primitive for a leaf, of the second child for an interior node,
+    /// the first child is always stored right after its parent.
+    index: usize,
+    leaf: bool,
+}
+
+/// Bounding volume hierarchy over primitives only known by their bounds.
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh { nodes: Vec::new() };
+        let mut indices: Vec<usize> = (0..bounds.len()).collect();
+        if !indices.is_empty() {
+            bvh.build_recursive(bounds, &mut indices);
+        }
+        bvh
+    }
+
+    fn build_recursive(&mut self, bounds: &[Aabb], indices: &mut [usize]) -> usize {
+        let node_bounds = indices[1..]
+            .iter()
+            .fold(bounds[indices[0]], |acc, &i| acc.union(&bounds[i]));
+        let node = self.nodes.len();
+        if indices.len() == 1 {
+            self.nodes.push(BvhNode {
+                bounds: node_bounds,
+                index: indices[0],
+                leaf: true,
+            });
+            return node;
+        }
+
+        // Median split along the axis where the centroids are the most spread.
+        let extent = node_bounds.max - node_bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let key = |i: &usize| {
+            let c = bounds[*i].centroid();
+            match axis {
+                0 => c.x,
+                1 => c.y,
+                _ => c.z,
+            }
+        };
+        indices.sort_by(|a, b| key(a).total_cmp(&key(b)));
+
+        self.nodes.push(BvhNode {
+            bounds: node_bounds,
+            index: 0,
+            leaf: false,
+        });
+        let (left, right) = indices.split_at_mut(indices.len() / 2);
+        self.build_recursive(bounds, left);
+        self.nodes[node].index = self.build_recursive(bounds, right);
+        node
+    }
+
+    /// Calls `intersect` on every primitive whose bounds the ray may hit before the closest
+    /// distance found so far, `intersect` returns the distance of a closer hit if any.
+    pub fn traverse<F>(&self, orig: &Vec3f32, dir: &Vec3f32, mut t_max: f32, mut intersect: F)
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inv_dir = Vec3f32::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let mut stack = [0; 64];
+        let mut stack_size = 1;
+        let mut visits = 0;
+        while stack_size > 0 {
+            stack_size -= 1;
+            visits += 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+            if !node.bounds.hit(orig, &inv_dir, t_max) {
+                continue;
+            }
+            if node.leaf {
+                if let Some(dist) = intersect(node.index) {
+                    t_max = t_max.min(dist);
+                }
+            } else {
+                stack[stack_size] = node.index;
+                stack[stack_size + 1] = node_index + 1;
+                stack_size += 2;
+            }
+        }
+        stats::count(Counter::BvhNodeVisits, visits);
+    }
+}
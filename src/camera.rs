@@ -0,0 +1,329 @@
+use crate::vec3::Vec3f32;
+
+/// Height of a full frame 35mm sensor, in meters, used to turn the field of view into a focal length.
+const SENSOR_HEIGHT: f32 = 0.024;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Aperture {
+    Circle,
+    /// Regular polygon formed by `blades` diaphragm blades, rotated by `rotation` radians.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance from the image center proportional to the angle from the view axis.
+    Equidistant,
+    /// Preserves solid angles, r = 2 f sin(theta / 2).
+    Equisolid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays, `height` is the extent of the view in scene units.
+    Orthographic {
+        height: f32,
+    },
+    /// Circular image fitting the image height and covering the camera field of view.
+    Fisheye(FisheyeMapping),
+    /// Full 360x180 degrees panorama using the same mapping as the env map lookup.
+    Equirectangular,
+    /// The six faces +x, -x, +y, -y, +z, -z side by side, following the OpenGL convention.
+    Cubemap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+    /// Red channel from the left eye, green and blue from the right eye.
+    Anaglyph,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    /// Distance between the eyes in scene units.
+    pub interocular: f32,
+    /// Distance at which both eyes see the same point, ignored by panoramas.
+    pub convergence: f32,
+}
+
+impl Stereo {
+    /// Copy of `camera` for the left or the right eye.
+    pub fn eye(&self, camera: &Camera, left: bool) -> Camera {
+        let half = self.interocular / 2.;
+        Camera {
+            eye_offset: if left { -half } else { half },
+            convergence: self.convergence,
+            ..*camera
+        }
+    }
+}
+
+/// Camera looking down the -z axis. With the perspective projection it is a thin lens camera,
+/// a zero aperture radius gives a pinhole camera.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub position: Vec3f32,
+    pub projection: Projection,
+    pub fov: f32,
+    pub aperture_radius: f32,
+    /// Distance along the view axis of the plane in perfect focus.
+    pub focus_distance: f32,
+    pub aperture: Aperture,
+    /// Displacement per unit of time, the camera is at `position` at time 0.
+    pub velocity: Vec3f32,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    /// Signed offset of a stereo eye along the x axis, 0 for a mono camera.
+    pub eye_offset: f32,
+    pub convergence: f32,
+}
+
+impl Camera {
+    pub fn new(position: Vec3f32, fov: f32) -> Camera {
+        Camera {
+            position,
+            projection: Projection::Perspective,
+            fov,
+            aperture_radius: 0.,
+            focus_distance: 1.,
+            aperture: Aperture::Circle,
+            velocity: Vec3f32::new(0., 0., 0.),
+            shutter_open: 0.,
+            shutter_close: 0.,
+            eye_offset: 0.,
+            convergence: 1.,
+        }
+    }
+
+    pub fn position_at(&self, time: f32) -> Vec3f32 {
+        self.position + self.velocity * time
+    }
+
+    /// Time within the shutter interval for the uniform number `u`.
+    pub fn sample_time(&self, u: f32) -> f32 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * u
+    }
+
+    /// Focal length in scene units (meters) of a full frame camera with this field of view.
+    pub fn focal_length(&self) -> f32 {
+        SENSOR_HEIGHT / (2. * (self.fov / 2.).tan())
+    }
+
+    pub fn set_f_stop(&mut self, f_stop: f32) {
+        self.aperture_radius = self.focal_length() / (2. * f_stop);
+    }
+
+    /// Direction through the point (`x`, `y`) of the image plane, in pixels from the top left corner.
+    pub fn pinhole_direction(&self, width: usize, height: usize, x: f32, y: f32) -> Vec3f32 {
+        let dir_x = x - width as f32 / 2.;
+        let dir_y = -y + height as f32 / 2.;
+        let dir_z = -(height as f32) / (2. * (self.fov / 2.).tan());
+        let mut dir = Vec3f32::new(dir_x, dir_y, dir_z);
+        dir.normalize();
+        dir
+    }
+
+    /// Light tracing projects points back on the image, only done for a mono pinhole camera
+    /// with the perspective projection.
+    pub fn supports_light_tracing(&self) -> bool {
+        self.projection == Projection::Perspective
+            && self.aperture_radius <= 0.
+            && self.eye_offset == 0.
+    }
+
+    /// Image position, in pixels, of the primary ray with direction `dir`.
+    pub fn raster_position(
+        &self,
+        width: usize,
+        height: usize,
+        dir: &Vec3f32,
+    ) -> Option<(f32, f32)> {
+        if dir.z >= 0. {
+            return None;
+        }
+        let focal = height as f32 / (2. * (self.fov / 2.).tan());
+        let x = width as f32 / 2. + dir.x / -dir.z * focal;
+        let y = height as f32 / 2. - dir.y / -dir.z * focal;
+        if x < 0. || y < 0. || x >= width as f32 || y >= height as f32 {
+            return None;
+        }
+        Some((x, y))
+    }
+
+    /// Importance and solid angle density of the primary ray with direction `dir`, both are
+    /// normalized over the whole image.
+    pub fn importance(&self, width: usize, height: usize, dir: &Vec3f32) -> (f32, f32) {
+        if self.raster_position(width, height, dir).is_none() {
+            return (0., 0.);
+        }
+        let tan = (self.fov / 2.).tan();
+        let area = 4. * tan * tan * width as f32 / height as f32;
+        let cos = -dir.z;
+        let pdf = 1. / (area * cos * cos * cos);
+        (pdf / cos, pdf)
+    }
+
+    /// Offset from the camera position and direction of the ray through the image point
+    /// (`x`, `y`) when the lens is ignored, `None` outside of a fisheye image circle.
+    pub fn projection_ray(
+        &self,
+        width: usize,
+        height: usize,
+        x: f32,
+        y: f32,
+    ) -> Option<(Vec3f32, Vec3f32)> {
+        let (w, h) = (width as f32, height as f32);
+        let zero = Vec3f32::new(0., 0., 0.);
+        let ray = match self.projection {
+            Projection::Perspective => (zero, self.pinhole_direction(width, height, x, y)),
+            Projection::Orthographic {
+                height: view_height,
+            } => {
+                let scale = view_height / h;
+                let offset = Vec3f32::new((x - w / 2.) * scale, (h / 2. - y) * scale, 0.);
+                (offset, Vec3f32::new(0., 0., -1.))
+            }
+            Projection::Fisheye(mapping) => {
+                let (u, v) = ((x - w / 2.) / (h / 2.), (h / 2. - y) / (h / 2.));
+                let r = (u * u + v * v).sqrt();
+                if r > 1. {
+                    return None;
+                }
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * self.fov / 2.,
+                    FisheyeMapping::Equisolid => 2. * (r * (self.fov / 4.).sin()).asin(),
+                };
+                let phi = v.atan2(u);
+                let dir = Vec3f32::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                (zero, dir)
+            }
+            Projection::Equirectangular => {
+                let phi = (x / w - 0.5) * 2. * std::f32::consts::PI;
+                let theta = y / h * std::f32::consts::PI;
+                let dir = Vec3f32::new(
+                    phi.cos() * theta.sin(),
+                    theta.cos(),
+                    phi.sin() * theta.sin(),
+                );
+                (zero, dir)
+            }
+            Projection::Cubemap => {
+                let face_width = w / 6.;
+                let face = ((x / face_width) as usize).min(5);
+                let a = 2. * (x - face as f32 * face_width) / face_width - 1.;
+                let b = 2. * y / h - 1.;
+                let mut dir = match face {
+                    0 => Vec3f32::new(1., -b, -a),
+                    1 => Vec3f32::new(-1., -b, a),
+                    2 => Vec3f32::new(a, 1., b),
+                    3 => Vec3f32::new(a, -1., -b),
+                    4 => Vec3f32::new(a, -b, 1.),
+                    _ => Vec3f32::new(-a, -b, -1.),
+                };
+                dir.normalize();
+                (zero, dir)
+            }
+        };
+        if self.eye_offset == 0. {
+            Some(ray)
+        } else {
+            Some(self.eye_ray(ray.0, ray.1))
+        }
+    }
+
+    /// Moves the ray of a mono camera to the eye given by `eye_offset`.
+    fn eye_ray(&self, offset: Vec3f32, dir: Vec3f32) -> (Vec3f32, Vec3f32) {
+        match self.projection {
+            Projection::Perspective => {
+                // Off-axis frustum, both eyes share the image plane at the convergence distance.
+                let eye = Vec3f32::new(self.eye_offset, 0., 0.);
+                let mut eye_dir = dir * (self.convergence / -dir.z) - eye;
+                eye_dir.normalize();
+                (offset + eye, eye_dir)
+            }
+            Projection::Equirectangular => {
+                // Omni-directional stereo, the eyes sit on a circle tangent to the horizontal
+                // view direction and merge toward the poles.
+                let eye = Vec3f32::new(-dir.z, 0., dir.x) * self.eye_offset;
+                (offset + eye, dir)
+            }
+            _ => (offset + Vec3f32::new(self.eye_offset, 0., 0.), dir),
+        }
+    }
+
+    /// Returns the origin and direction of the ray through the image point (`x`, `y`),
+    /// `lens` holds three uniform numbers used to pick a point on the aperture.
+    pub fn generate_ray(
+        &self,
+        width: usize,
+        height: usize,
+        x: f32,
+        y: f32,
+        lens: [f32; 3],
+        time: f32,
+    ) -> Option<(Vec3f32, Vec3f32)> {
+        let position = self.position_at(time);
+        let (offset, dir) = self.projection_ray(width, height, x, y)?;
+        if self.aperture_radius <= 0. || self.projection != Projection::Perspective {
+            return Some((position + offset, dir));
+        }
+        // The lens is centered on the eye, which the offset moves for stereo.
+        let eye = position + offset;
+        let focus_point = eye + dir * (self.focus_distance / -dir.z);
+        let (lens_x, lens_y) = self.sample_aperture(lens);
+        let orig = eye + Vec3f32::new(lens_x, lens_y, 0.) * self.aperture_radius;
+        let mut lens_dir = focus_point - orig;
+        lens_dir.normalize();
+        Some((orig, lens_dir))
+    }
+
+    /// Point on the unit aperture.
+    fn sample_aperture(&self, u: [f32; 3]) -> (f32, f32) {
+        match self.aperture {
+            Aperture::Circle => concentric_disk(u[0], u[1]),
+            Aperture::Polygon { blades, rotation } => {
+                let blades = blades.max(3);
+                // Pick a triangle (center, vertex k, vertex k + 1) then a point inside it.
+                let k = ((u[0] * blades as f32) as u32).min(blades - 1);
+                let step = 2. * std::f32::consts::PI / blades as f32;
+                let a0 = rotation + step * k as f32;
+                let a1 = a0 + step;
+                let (mut s, mut t) = (u[1], u[2]);
+                if s + t > 1. {
+                    s = 1. - s;
+                    t = 1. - t;
+                }
+                (s * a0.cos() + t * a1.cos(), s * a0.sin() + t * a1.sin())
+            }
+        }
+    }
+}
+
+// http://psgraphics.blogspot.com/2011/01/improved-code-for-concentric-map.html
+fn concentric_disk(u: f32, v: f32) -> (f32, f32) {
+    let a = 2. * u - 1.;
+    let b = 2. * v - 1.;
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+    let (r, phi) = if a * a > b * b {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    (r * phi.cos(), r * phi.sin())
+}
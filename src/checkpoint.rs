@@ -0,0 +1,177 @@
+//! Checkpoints of long renders, written between two rounds of samples so that a killed render
+//! can go on from there. A resumed render gives the same image as an uninterrupted one since
+//! every pixel keeps its own random sequence and statistics.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use crate::color::Rgb;
+use crate::sampler::{PixelState, PixelStats, Rng};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// Settings the render depends on, a checkpoint only resumes the same render.
+    pub fingerprint: String,
+    pub width: usize,
+    pub height: usize,
+    /// Pass in progress.
+    pub pass: u32,
+    /// Average of the finished passes and their total number of samples per pixel.
+    pub framebuffer: Vec<Rgb>,
+    pub sample_counts: Vec<u32>,
+    /// State of the pixels in the pass in progress.
+    pub pixels: Vec<PixelState>,
+    /// Sums of the AOV samples of the pass, empty when none are recorded.
+    pub aovs: Vec<Rgb>,
+    /// Fixed point light splatted by the bidirectional integrator during the pass.
+    pub splats: Vec<[i64; 3]>,
+}
+
+fn write_rgbs(out: &mut impl Write, values: &[Rgb]) -> io::Result<()> {
+    for v in values {
+        for c in &[v.r, v.g, v.b] {
+            out.write_all(&c.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Little endian values read one after another.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + n)
+            .ok_or_else(|| String::from("truncated checkpoint"))?;
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    fn rgbs(&mut self, count: usize) -> Result<Vec<Rgb>, String> {
+        (0..count)
+            .map(|_| {
+                let c = [self.u32()?, self.u32()?, self.u32()?];
+                Ok(Rgb::new(
+                    f32::from_bits(c[0]),
+                    f32::from_bits(c[1]),
+                    f32::from_bits(c[2]),
+                ))
+            })
+            .collect()
+    }
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint next to `path` first, a render killed while writing leaves the
+    /// previous checkpoint intact.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let temporary = format!("{}.tmp", path);
+        {
+            let mut file = BufWriter::new(File::create(&temporary)?);
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            file.write_all(&(self.fingerprint.len() as u32).to_le_bytes())?;
+            file.write_all(self.fingerprint.as_bytes())?;
+            for v in &[self.width as u32, self.height as u32, self.pass] {
+                file.write_all(&v.to_le_bytes())?;
+            }
+            write_rgbs(&mut file, &self.framebuffer)?;
+            for count in &self.sample_counts {
+                file.write_all(&count.to_le_bytes())?;
+            }
+            for pixel in &self.pixels {
+                for word in &pixel.rng.to_words() {
+                    file.write_all(&word.to_le_bytes())?;
+                }
+                for word in &pixel.stats.to_words() {
+                    file.write_all(&word.to_le_bytes())?;
+                }
+            }
+            file.write_all(&[!self.aovs.is_empty() as u8])?;
+            write_rgbs(&mut file, &self.aovs)?;
+            for splat in &self.splats {
+                for c in splat {
+                    file.write_all(&c.to_le_bytes())?;
+                }
+            }
+            file.flush()?;
+        }
+        fs::rename(&temporary, path)
+    }
+
+    pub fn read(path: &str, aov_count: usize) -> Result<Checkpoint, String> {
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            position: 0,
+        };
+        let checkpoint = (|| {
+            if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+                return Err(String::from("not a checkpoint of this version"));
+            }
+            let length = reader.u32()? as usize;
+            let fingerprint = String::from_utf8_lossy(reader.take(length)?).into_owned();
+            let width = reader.u32()? as usize;
+            let height = reader.u32()? as usize;
+            let pass = reader.u32()?;
+            let count = width * height;
+            let framebuffer = reader.rgbs(count)?;
+            let sample_counts = (0..count).map(|_| reader.u32()).collect::<Result<_, _>>()?;
+            let pixels = (0..count)
+                .map(|_| {
+                    let rng = Rng::from_words([reader.u64()?, reader.u64()?]);
+                    let mut words = [0; 6];
+                    for word in words.iter_mut() {
+                        *word = reader.u32()?;
+                    }
+                    Ok(PixelState {
+                        rng,
+                        stats: PixelStats::from_words(words),
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            let aovs = match reader.take(1)?[0] {
+                0 => Vec::new(),
+                _ => reader.rgbs(count * aov_count)?,
+            };
+            let splats = (0..count)
+                .map(|_| {
+                    Ok([
+                        reader.u64()? as i64,
+                        reader.u64()? as i64,
+                        reader.u64()? as i64,
+                    ])
+                })
+                .collect::<Result<_, String>>()?;
+            Ok(Checkpoint {
+                fingerprint,
+                width,
+                height,
+                pass,
+                framebuffer,
+                sample_counts,
+                pixels,
+                aovs,
+                splats,
+            })
+        })();
+        checkpoint.map_err(|err: String| format!("{}: {}", path, err))
+    }
+}
@@ -29,21 +29,21 @@ impl Add<Rgb> for Rgb {
         Rgb::new(self.r + other.r, self.g + other.g, self.b + other.b)
     }
 }
-impl<'a> Add<Rgb> for &'a Rgb {
+impl Add<Rgb> for &Rgb {
     type Output = Rgb; // https://stackoverflow.com/questions/39115363/whg-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: Rgb) -> Rgb {
         Rgb::new(self.r + other.r, self.g + other.g, self.b + other.b)
     }
 }
-impl<'a> Add<&'a Rgb> for Rgb {
+impl Add<&Rgb> for Rgb {
     type Output = Rgb; // https://stackoverflow.com/questions/39115363/whg-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: &Rgb) -> Rgb {
         Rgb::new(self.r + other.r, self.g + other.g, self.b + other.b)
     }
 }
-impl<'a> Add<&'a Rgb> for &'a Rgb {
+impl Add<&Rgb> for &Rgb {
     type Output = Rgb; // https://stackoverflow.com/questions/39115363/whg-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: &Rgb) -> Rgb {
@@ -58,21 +58,21 @@ impl Add<f32> for Rgb {
         Rgb::new(self.r + coef, self.g + coef, self.b + coef)
     }
 }
-impl<'a> Add<f32> for &'a Rgb {
+impl Add<f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn add(self, coef: f32) -> Rgb {
         Rgb::new(self.r + coef, self.g + coef, self.b + coef)
     }
 }
-impl<'a> Add<&'a f32> for Rgb {
+impl Add<&f32> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn add(self, coef: &f32) -> Rgb {
         Rgb::new(self.r + *coef, self.g + *coef, self.b + *coef)
     }
 }
-impl<'a> Add<&'a f32> for &'a Rgb {
+impl Add<&f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn add(self, coef: &f32) -> Rgb {
@@ -87,21 +87,21 @@ impl Sub<Rgb> for Rgb {
         Rgb::new(self.r - other.r, self.g - other.g, self.b - other.b)
     }
 }
-impl<'a> Sub<Rgb> for &'a Rgb {
+impl Sub<Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, other: Rgb) -> Rgb {
         Rgb::new(self.r - other.r, self.g - other.g, self.b - other.b)
     }
 }
-impl<'a> Sub<&'a Rgb> for Rgb {
+impl Sub<&Rgb> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, other: &Rgb) -> Rgb {
         Rgb::new(self.r - other.r, self.g - other.g, self.b - other.b)
     }
 }
-impl<'a> Sub<&'a Rgb> for &'a Rgb {
+impl Sub<&Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, other: &Rgb) -> Rgb {
@@ -116,21 +116,21 @@ impl Sub<f32> for Rgb {
         Rgb::new(self.r - coef, self.g - coef, self.b - coef)
     }
 }
-impl<'a> Sub<f32> for &'a Rgb {
+impl Sub<f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, coef: f32) -> Rgb {
         Rgb::new(self.r - coef, self.g - coef, self.b - coef)
     }
 }
-impl<'a> Sub<&'a f32> for Rgb {
+impl Sub<&f32> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, coef: &f32) -> Rgb {
         Rgb::new(self.r - *coef, self.g - *coef, self.b - *coef)
     }
 }
-impl<'a> Sub<&'a f32> for &'a Rgb {
+impl Sub<&f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn sub(self, coef: &f32) -> Rgb {
@@ -145,21 +145,21 @@ impl Mul<Rgb> for Rgb {
         Rgb::new(self.r * other.r, self.g * other.g, self.b * other.b)
     }
 }
-impl<'a> Mul<Rgb> for &'a Rgb {
+impl Mul<Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, other: Rgb) -> Rgb {
         Rgb::new(self.r * other.r, self.g * other.g, self.b * other.b)
     }
 }
-impl<'a> Mul<&'a Rgb> for Rgb {
+impl Mul<&Rgb> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, other: &Rgb) -> Rgb {
         Rgb::new(self.r * other.r, self.g * other.g, self.b * other.b)
     }
 }
-impl<'a> Mul<&'a Rgb> for &'a Rgb {
+impl Mul<&Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, other: &Rgb) -> Rgb {
@@ -174,21 +174,21 @@ impl Mul<f32> for Rgb {
         Rgb::new(self.r * coef, self.g * coef, self.b * coef)
     }
 }
-impl<'a> Mul<f32> for &'a Rgb {
+impl Mul<f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, coef: f32) -> Rgb {
         Rgb::new(self.r * coef, self.g * coef, self.b * coef)
     }
 }
-impl<'a> Mul<&'a f32> for Rgb {
+impl Mul<&f32> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, coef: &f32) -> Rgb {
         Rgb::new(self.r * *coef, self.g * *coef, self.b * *coef)
     }
 }
-impl<'a> Mul<&'a f32> for &'a Rgb {
+impl Mul<&f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn mul(self, coef: &f32) -> Rgb {
@@ -203,21 +203,21 @@ impl Div<Rgb> for Rgb {
         Rgb::new(self.r / other.r, self.g / other.g, self.b / other.b)
     }
 }
-impl<'a> Div<Rgb> for &'a Rgb {
+impl Div<Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, other: Rgb) -> Rgb {
         Rgb::new(self.r / other.r, self.g / other.g, self.b / other.b)
     }
 }
-impl<'a> Div<&'a Rgb> for Rgb {
+impl Div<&Rgb> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, other: &Rgb) -> Rgb {
         Rgb::new(self.r / other.r, self.g / other.g, self.b / other.b)
     }
 }
-impl<'a> Div<&'a Rgb> for &'a Rgb {
+impl Div<&Rgb> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, other: &Rgb) -> Rgb {
@@ -232,21 +232,21 @@ impl Div<f32> for Rgb {
         Rgb::new(self.r / coef, self.g / coef, self.b / coef)
     }
 }
-impl<'a> Div<f32> for &'a Rgb {
+impl Div<f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, coef: f32) -> Rgb {
         Rgb::new(self.r / coef, self.g / coef, self.b / coef)
     }
 }
-impl<'a> Div<&'a f32> for Rgb {
+impl Div<&f32> for Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, coef: &f32) -> Rgb {
         Rgb::new(self.r / *coef, self.g / *coef, self.b / *coef)
     }
 }
-impl<'a> Div<&'a f32> for &'a Rgb {
+impl Div<&f32> for &Rgb {
     type Output = Rgb;
     #[inline(always)]
     fn div(self, coef: &f32) -> Rgb {
@@ -259,4 +259,31 @@ impl Rgb {
     pub fn new(r: f32, g: f32, b: f32) -> Rgb {
         Rgb { r, g, b }
     }
+
+    #[inline(always)]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Color as written to 8-bit images, in [0, 1], the ones brighter than white are scaled
+    /// down to keep their hue.
+    pub fn to_display(self) -> Rgb {
+        let max = self.r.max(self.g.max(self.b));
+        let v = if max > 1. { self / max } else { self };
+        Rgb::new(v.r.clamp(0., 1.), v.g.clamp(0., 1.), v.b.clamp(0., 1.))
+    }
+
+    /// Color of a pixel of an 8-bit image, the inverse of `to_display` up to white.
+    pub fn from_rgb8(pixel: [u8; 3]) -> Rgb {
+        Rgb::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) / 255.
+    }
+}
+
+/// Blue to red ramp used to display scalar data such as sample counts, `t` in [0, 1].
+pub fn false_color(t: f32) -> Rgb {
+    let t = t.clamp(0., 1.);
+    let r = (1.5 - (4. * t - 3.).abs()).clamp(0., 1.);
+    let g = (1.5 - (4. * t - 2.).abs()).clamp(0., 1.);
+    let b = (1.5 - (4. * t - 1.).abs()).clamp(0., 1.);
+    Rgb::new(r, g, b)
 }
@@ -0,0 +1,132 @@
+//! Edge-avoiding à-trous wavelet filter removing the noise of renders with few samples per
+//! pixel, guided by the albedo and normal AOVs so that edges and textures stay sharp.
+//! https://jo.dreggn.org/home/2010_atrous.pdf
+
+use rayon::prelude::*;
+
+use crate::color::Rgb;
+
+/// B3 spline kernel, spread by a power of two at every iteration.
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseSettings {
+    /// Passes of the filter, the footprint doubles at each one.
+    pub iterations: u32,
+    /// Tolerance on the difference of tone mapped colors, halved at each iteration.
+    pub color_sigma: f32,
+    pub normal_sigma: f32,
+    pub albedo_sigma: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> DenoiseSettings {
+        DenoiseSettings {
+            iterations: 5,
+            color_sigma: 0.5,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+fn distance2(a: &Rgb, b: &Rgb) -> f32 {
+    let d = *a - *b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+/// Compresses the HDR range so that the color tolerance means the same in highlights and
+/// shadows.
+fn tone_map(c: &Rgb) -> Rgb {
+    *c / (1. + c.r.max(c.g).max(c.b).max(0.))
+}
+
+/// Returns the filtered `color`, all images have `width * height` pixels. The lighting is
+/// filtered apart from the albedo, which is multiplied back afterwards. Pixels without a
+/// normal see the background, which is left as it is.
+pub fn denoise(
+    width: usize,
+    height: usize,
+    color: &[Rgb],
+    albedo: &[Rgb],
+    normal: &[Rgb],
+    settings: &DenoiseSettings,
+) -> Vec<Rgb> {
+    let surface: Vec<bool> = normal
+        .iter()
+        .map(|n| n.r != 0. || n.g != 0. || n.b != 0.)
+        .collect();
+    // Black surfaces cannot be demodulated.
+    let divisor: Vec<Rgb> = albedo
+        .iter()
+        .map(|a| {
+            if a.r.max(a.g).max(a.b) < 1e-3 {
+                Rgb::new(1., 1., 1.)
+            } else {
+                Rgb::new(a.r.max(1e-3), a.g.max(1e-3), a.b.max(1e-3))
+            }
+        })
+        .collect();
+    let mut lighting: Vec<Rgb> = color
+        .iter()
+        .zip(&divisor)
+        .map(|(c, d)| Rgb::new(c.r / d.r, c.g / d.g, c.b / d.b))
+        .collect();
+
+    let mut filtered = lighting.clone();
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let color_sigma2 = (settings.color_sigma / step as f32).powi(2);
+        let normal_sigma2 = settings.normal_sigma * settings.normal_sigma;
+        let albedo_sigma2 = settings.albedo_sigma * settings.albedo_sigma;
+        let tone_mapped: Vec<Rgb> = lighting.iter().map(tone_map).collect();
+        filtered
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    let center = y * width + x;
+                    if !surface[center] {
+                        *out = lighting[center];
+                        continue;
+                    }
+                    let mut sum = Rgb::new(0., 0., 0.);
+                    let mut weights = 0.;
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        let sy = y as isize + (j as isize - 2) * step;
+                        if sy < 0 || sy >= height as isize {
+                            continue;
+                        }
+                        for (i, kx) in KERNEL.iter().enumerate() {
+                            let sx = x as isize + (i as isize - 2) * step;
+                            if sx < 0 || sx >= width as isize {
+                                continue;
+                            }
+                            let sample = sy as usize * width + sx as usize;
+                            if !surface[sample] {
+                                continue;
+                            }
+                            let w = ky
+                                * kx
+                                * (-distance2(&tone_mapped[center], &tone_mapped[sample])
+                                    / color_sigma2
+                                    - distance2(&normal[center], &normal[sample]) / normal_sigma2
+                                    - distance2(&albedo[center], &albedo[sample]) / albedo_sigma2)
+                                    .exp();
+                            sum = sum + lighting[sample] * w;
+                            weights += w;
+                        }
+                    }
+                    // The center weighs at least 9/64, the sum is never zero.
+                    *out = sum / weights;
+                }
+            });
+        std::mem::swap(&mut lighting, &mut filtered);
+    }
+
+    lighting
+        .iter()
+        .zip(&divisor)
+        .map(|(l, d)| Rgb::new(l.r * d.r, l.g * d.g, l.b * d.b))
+        .collect()
+}
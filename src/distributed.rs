@@ -0,0 +1,493 @@
+//! Rendering over the network. A coordinator hands out the tiles of every pass to worker
+//! processes connected over TCP, which load the same scene and send back the float colors of
+//! the tiles. The tiles of a worker which disconnects or stops answering go to the others.
+//!
+//! Every message is a tag byte followed by little endian fields.
+
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::color::Rgb;
+use crate::stats;
+use crate::tile::Tile;
+
+const VERSION: u32 = 1;
+
+/// How long a worker keeps trying to reach a coordinator which is not listening yet.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Samples of the pixels of one tile of a pass, row by row.
+#[derive(Debug, Clone)]
+pub struct TileResult {
+    pub pass: u32,
+    pub tile: Tile,
+    /// Mean colors, without the light splatted by the bidirectional integrator.
+    pub colors: Vec<Rgb>,
+    pub sample_counts: Vec<u32>,
+    /// Averaged AOVs of the pixels, empty when they are not recorded.
+    pub aovs: Vec<Rgb>,
+    /// Fixed point light splatted anywhere in the image while sampling the tile.
+    pub splats: Vec<(u32, [i64; 3])>,
+}
+
+impl TileResult {
+    /// Whether the fields have the sizes the coordinator merges, `aovs_per_pixel` is 0 when
+    /// the AOVs are not recorded.
+    fn fits(&self, image_pixels: usize, aovs_per_pixel: usize) -> bool {
+        let pixels = self.tile.width * self.tile.height;
+        self.colors.len() == pixels
+            && self.sample_counts.len() == pixels
+            && self.aovs.len() == aovs_per_pixel * pixels
+            && self
+                .splats
+                .iter()
+                .all(|(index, _)| (*index as usize) < image_pixels)
+    }
+}
+
+#[derive(Debug)]
+enum Message {
+    /// Arguments of the render, sent by the coordinator to every new worker.
+    Job(Vec<String>),
+    /// Number of tiles the worker renders at once.
+    Ready(u32),
+    Work(u32, Tile),
+    Result(TileResult),
+    /// No more work, the worker answers with its counters.
+    Done,
+    Counters(Vec<u64>),
+}
+
+fn write_u32(out: &mut impl Write, v: u32) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn write_u64(out: &mut impl Write, v: u64) -> io::Result<()> {
+    out.write_all(&v.to_le_bytes())
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_tile(out: &mut impl Write, tile: &Tile) -> io::Result<()> {
+    for v in &[tile.x, tile.y, tile.width, tile.height] {
+        write_u32(out, *v as u32)?;
+    }
+    Ok(())
+}
+
+fn read_tile(input: &mut impl Read) -> io::Result<Tile> {
+    Ok(Tile {
+        x: read_u32(input)? as usize,
+        y: read_u32(input)? as usize,
+        width: read_u32(input)? as usize,
+        height: read_u32(input)? as usize,
+    })
+}
+
+fn write_rgbs(out: &mut impl Write, values: &[Rgb]) -> io::Result<()> {
+    write_u32(out, values.len() as u32)?;
+    for v in values {
+        for c in &[v.r, v.g, v.b] {
+            write_u32(out, c.to_bits())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_rgbs(input: &mut impl Read) -> io::Result<Vec<Rgb>> {
+    let count = read_u32(input)?;
+    (0..count)
+        .map(|_| {
+            let mut c = [0.; 3];
+            for v in c.iter_mut() {
+                *v = f32::from_bits(read_u32(input)?);
+            }
+            Ok(Rgb::new(c[0], c[1], c[2]))
+        })
+        .collect()
+}
+
+fn write_message(out: &mut impl Write, message: &Message) -> io::Result<()> {
+    match message {
+        Message::Job(args) => {
+            out.write_all(&[0])?;
+            write_u32(out, VERSION)?;
+            write_u32(out, args.len() as u32)?;
+            for arg in args {
+                write_u32(out, arg.len() as u32)?;
+                out.write_all(arg.as_bytes())?;
+            }
+        }
+        Message::Ready(threads) => {
+            out.write_all(&[1])?;
+            write_u32(out, *threads)?;
+        }
+        Message::Work(pass, tile) => {
+            out.write_all(&[2])?;
+            write_u32(out, *pass)?;
+            write_tile(out, tile)?;
+        }
+        Message::Result(result) => {
+            out.write_all(&[3])?;
+            write_u32(out, result.pass)?;
+            write_tile(out, &result.tile)?;
+            write_rgbs(out, &result.colors)?;
+            for count in &result.sample_counts {
+                write_u32(out, *count)?;
+            }
+            write_rgbs(out, &result.aovs)?;
+            write_u32(out, result.splats.len() as u32)?;
+            for (index, splat) in &result.splats {
+                write_u32(out, *index)?;
+                for c in splat {
+                    write_u64(out, *c as u64)?;
+                }
+            }
+        }
+        Message::Done => out.write_all(&[4])?,
+        Message::Counters(counters) => {
+            out.write_all(&[5])?;
+            write_u32(out, counters.len() as u32)?;
+            for v in counters {
+                write_u64(out, *v)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+fn read_message(input: &mut impl Read) -> io::Result<Message> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let mut tag = [0];
+    input.read_exact(&mut tag).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(err.kind(), "connection closed"),
+        _ => err,
+    })?;
+    Ok(match tag[0] {
+        0 => {
+            if read_u32(input)? != VERSION {
+                return Err(invalid("coordinator of another version"));
+            }
+            let count = read_u32(input)?;
+            let args = (0..count)
+                .map(|_| {
+                    let mut bytes = vec![0; read_u32(input)? as usize];
+                    input.read_exact(&mut bytes)?;
+                    String::from_utf8(bytes).map_err(|_| invalid("argument is not UTF-8"))
+                })
+                .collect::<io::Result<_>>()?;
+            Message::Job(args)
+        }
+        1 => Message::Ready(read_u32(input)?),
+        2 => Message::Work(read_u32(input)?, read_tile(input)?),
+        3 => {
+            let pass = read_u32(input)?;
+            let tile = read_tile(input)?;
+            let colors = read_rgbs(input)?;
+            let sample_counts = (0..colors.len())
+                .map(|_| read_u32(input))
+                .collect::<io::Result<_>>()?;
+            let aovs = read_rgbs(input)?;
+            let count = read_u32(input)?;
+            let splats = (0..count)
+                .map(|_| {
+                    let index = read_u32(input)?;
+                    let mut splat = [0; 3];
+                    for c in splat.iter_mut() {
+                        *c = read_u64(input)? as i64;
+                    }
+                    Ok((index, splat))
+                })
+                .collect::<io::Result<_>>()?;
+            Message::Result(TileResult {
+                pass,
+                tile,
+                colors,
+                sample_counts,
+                aovs,
+                splats,
+            })
+        }
+        4 => Message::Done,
+        5 => {
+            let count = read_u32(input)?;
+            let counters = (0..count)
+                .map(|_| read_u64(input))
+                .collect::<io::Result<_>>()?;
+            Message::Counters(counters)
+        }
+        _ => return Err(invalid("unknown message")),
+    })
+}
+
+/// Tiles waiting for a worker and the number of tiles not merged yet.
+struct Queue {
+    pending: VecDeque<(u32, Tile)>,
+    remaining: usize,
+}
+
+/// Renders `passes` passes of `tiles` on the workers connecting to `address`, which get
+/// `args` to load the scene with. Every result goes to `merge` as it comes, a tile is only
+/// merged once even if it was handed out again. A result without `aovs_per_pixel` AOVs per
+/// pixel or splatting outside of the `image_pixels` pixels drops its worker.
+#[allow(clippy::too_many_arguments)]
+pub fn coordinate(
+    address: &str,
+    args: &[String],
+    tiles: &[Tile],
+    passes: u32,
+    image_pixels: usize,
+    aovs_per_pixel: usize,
+    timeout: Duration,
+    merge: &mut (dyn FnMut(TileResult) + Send),
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    println!("Waiting for workers on {}", listener.local_addr()?);
+    let queue = Mutex::new(Queue {
+        pending: (0..passes)
+            .flat_map(|pass| tiles.iter().map(move |tile| (pass, *tile)))
+            .collect(),
+        remaining: passes as usize * tiles.len(),
+    });
+    let changed = Condvar::new();
+    let merge = Mutex::new(merge);
+    thread::scope(|s| {
+        while queue.lock().unwrap().remaining > 0 {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let (queue, changed, merge) = (&queue, &changed, &merge);
+                    s.spawn(move || {
+                        let sizes = (image_pixels, aovs_per_pixel);
+                        let result = serve(
+                            stream,
+                            args,
+                            sizes,
+                            timeout,
+                            queue,
+                            changed,
+                            &mut |result| (merge.lock().unwrap())(result),
+                        );
+                        if let Err(err) = result {
+                            println!("Worker {} dropped: {}", peer, err);
+                        }
+                    });
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Hands out tiles to the worker on `stream` until every tile is merged. The tiles the worker
+/// holds when it fails go back to the front of the queue. `sizes` are the pixels of the image
+/// and the AOVs per pixel of the results.
+fn serve(
+    stream: TcpStream,
+    args: &[String],
+    sizes: (usize, usize),
+    timeout: Duration,
+    queue: &Mutex<Queue>,
+    changed: &Condvar,
+    merge: &mut dyn FnMut(TileResult),
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let peer = stream.peer_addr()?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+    write_message(&mut writer, &Message::Job(args.to_vec()))?;
+    let threads = match read_message(&mut reader)? {
+        Message::Ready(threads) => threads.max(1) as usize,
+        other => return Err(unexpected(&other)),
+    };
+    println!("Worker {} connected, {} threads", peer, threads);
+
+    let mut in_flight: Vec<(u32, Tile)> = Vec::new();
+    let result = (|| loop {
+        // Twice as many tiles as threads keep the worker busy while results travel.
+        let handed_out: Vec<(u32, Tile)> = {
+            let mut queue = queue.lock().unwrap();
+            let count = (2 * threads)
+                .saturating_sub(in_flight.len())
+                .min(queue.pending.len());
+            queue.pending.drain(..count).collect()
+        };
+        for work in handed_out {
+            in_flight.push(work);
+            write_message(&mut writer, &Message::Work(work.0, work.1))?;
+        }
+        if in_flight.is_empty() {
+            let queue = queue.lock().unwrap();
+            if queue.remaining == 0 {
+                drop(queue);
+                write_message(&mut writer, &Message::Done)?;
+                if let Message::Counters(counters) = read_message(&mut reader)? {
+                    stats::add_all(&counters);
+                }
+                return Ok(());
+            }
+            // Waits for tiles dropped by another worker or the end of the render.
+            drop(changed.wait_timeout(queue, POLL_INTERVAL).unwrap());
+            continue;
+        }
+        match read_message(&mut reader)? {
+            Message::Result(result) => {
+                let key = (result.pass, result.tile);
+                let position = in_flight.iter().position(|work| *work == key);
+                let position =
+                    position.ok_or_else(|| unexpected(&Message::Result(result.clone())))?;
+                if !result.fits(sizes.0, sizes.1) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("result of tile {} has the wrong size", result.tile),
+                    ));
+                }
+                in_flight.swap_remove(position);
+                merge(result);
+                queue.lock().unwrap().remaining -= 1;
+                changed.notify_all();
+            }
+            other => return Err(unexpected(&other)),
+        }
+    })();
+    if result.is_err() && !in_flight.is_empty() {
+        let mut queue = queue.lock().unwrap();
+        for work in in_flight.into_iter().rev() {
+            queue.pending.push_front(work);
+        }
+        changed.notify_all();
+    }
+    result
+}
+
+fn unexpected(message: &Message) -> io::Error {
+    let name = match message {
+        Message::Job(_) => "job",
+        Message::Ready(_) => "ready",
+        Message::Work(..) => "work",
+        Message::Result(_) => "result",
+        Message::Done => "done",
+        Message::Counters(_) => "counters",
+    };
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {} message", name),
+    )
+}
+
+/// Connection of a worker process to its coordinator.
+pub struct Worker {
+    reader: BufReader<TcpStream>,
+    writer: Mutex<BufWriter<TcpStream>>,
+}
+
+impl Worker {
+    /// Connects to the coordinator at `address`, which may not be listening yet, and returns
+    /// the arguments of its render.
+    pub fn connect(address: &str) -> io::Result<(Worker, Vec<String>)> {
+        let start = Instant::now();
+        let stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) if start.elapsed() < CONNECT_TIMEOUT => thread::sleep(POLL_INTERVAL * 4),
+                Err(err) => return Err(err),
+            }
+        };
+        stream.set_nodelay(true)?;
+        let mut worker = Worker {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: Mutex::new(BufWriter::new(stream)),
+        };
+        match read_message(&mut worker.reader)? {
+            Message::Job(args) => Ok((worker, args)),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Renders the tiles sent by the coordinator on `threads` threads until it has no more
+    /// work. Each thread keeps its own state, made by `thread_state`.
+    pub fn run<S>(
+        self,
+        threads: usize,
+        thread_state: impl Fn() -> S + Sync,
+        render: impl Fn(&mut S, u32, &Tile) -> TileResult + Sync,
+    ) -> io::Result<()> {
+        let threads = threads.max(1);
+        let Worker { mut reader, writer } = self;
+        write_message(
+            &mut *writer.lock().unwrap(),
+            &Message::Ready(threads as u32),
+        )?;
+        let (sender, receiver) = mpsc::channel::<(u32, Tile)>();
+        let receiver = Mutex::new(receiver);
+        let failure = Mutex::new(None);
+        let (writer_ref, render, thread_state) = (&writer, &render, &thread_state);
+        let (receiver_ref, failure_ref) = (&receiver, &failure);
+        let result = thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(move || {
+                    let mut state = thread_state();
+                    loop {
+                        // The lock is released as soon as a tile is received.
+                        let next = receiver_ref.lock().unwrap().recv();
+                        let (pass, tile) = match next {
+                            Ok(work) => work,
+                            Err(_) => break,
+                        };
+                        let result = render(&mut state, pass, &tile);
+                        let sent = write_message(
+                            &mut *writer_ref.lock().unwrap(),
+                            &Message::Result(result),
+                        );
+                        if let Err(err) = sent {
+                            failure_ref.lock().unwrap().get_or_insert(err);
+                        }
+                    }
+                });
+            }
+            let received = (|| loop {
+                match read_message(&mut reader)? {
+                    Message::Work(pass, tile) => {
+                        if sender.send((pass, tile)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Message::Done => return Ok(()),
+                    other => return Err(unexpected(&other)),
+                }
+            })();
+            // The threads finish their tiles and stop.
+            drop(sender);
+            received
+        });
+        result?;
+        if let Some(err) = failure.into_inner().unwrap() {
+            return Err(err);
+        }
+        let counters = stats::snapshot().counters().to_vec();
+        let mut writer = writer.into_inner().unwrap();
+        write_message(&mut writer, &Message::Counters(counters))
+    }
+}
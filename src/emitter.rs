@@ -0,0 +1,108 @@
+//! Emissive surfaces and their sampling by area for next-event estimation.
+
+use std::f32::consts::PI;
+
+use crate::bsdf::sample_uniform_cone;
+use crate::color::Rgb;
+use crate::medium::shadow_transmittance;
+use crate::sampler::Rng;
+use crate::scene::{scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Light given off by the outside of a surface, `color * strength` times the texture.
+#[derive(Debug, Clone, Copy)]
+pub struct Emission {
+    pub color: Rgb,
+    pub strength: f32,
+    /// Index in `Scene::textures`, wrapped around spheres like the env map around the scene.
+    pub texture: Option<usize>,
+}
+
+impl Emission {
+    /// Radiance leaving the surface with outward normal `n`.
+    pub fn radiance(&self, scene: &Scene, n: &Vec3f32) -> Rgb {
+        let radiance = self.color * self.strength;
+        match self.texture {
+            Some(index) => radiance * scene.textures[index].lookup(n),
+            None => radiance,
+        }
+    }
+}
+
+/// Calls `f` with the direction toward one point sampled on every emissive sphere and the
+/// light arriving from it at `p`, in the units of the point light intensities of Whitted
+/// shading: the irradiance it gives at normal incidence divided by pi.
+///
+/// The point is drawn uniformly over the area of the half of the sphere facing `p`.
+#[allow(clippy::too_many_arguments)]
+pub fn sample_emitters<F>(
+    scene: &Scene,
+    p: &Vec3f32,
+    n: &Vec3f32,
+    medium: Option<usize>,
+    time: f32,
+    rng: &mut Rng,
+    mut f: F,
+) where
+    F: FnMut(Vec3f32, Rgb),
+{
+    for &index in &scene.emitters {
+        let sphere = &scene.spheres[index];
+        let emission = match sphere.material.emission {
+            Some(emission) => emission,
+            None => continue,
+        };
+        let center = sphere.center_at(time);
+        let mut axis = p - center;
+        if axis.norm() <= sphere.radius * 1.001 {
+            continue;
+        }
+        axis.normalize();
+        let normal = sample_uniform_cone(&axis, 0., rng.next_f32(), rng.next_f32());
+        let point = center + normal * sphere.radius;
+
+        let mut dir = point - p;
+        let distance = dir.norm();
+        dir.normalize();
+        let cos_light = -dir.dot_product(&normal);
+        if cos_light <= 0. {
+            continue;
+        }
+        let orig = if dir.dot_product(n) < 0. {
+            p - n * 1e-3
+        } else {
+            p + n * 1e-3
+        };
+        // Stops short of the emitter, anything hit before it casts a shadow.
+        let distance_to_light = (point - orig).norm() - 1e-3;
+        let transmittance = if scene.media.is_empty() {
+            let mut hit = Vec3f32::new(0., 0., 0.);
+            let mut hit_n = Vec3f32::new(0., 0., 0.);
+            let mut material = Material::default();
+            stats::count(Counter::ShadowRays, 1);
+            if scene_intersect(
+                &orig,
+                &dir,
+                time,
+                scene,
+                &mut hit,
+                &mut hit_n,
+                &mut material,
+            ) && (hit - orig).norm() < distance_to_light
+            {
+                continue;
+            }
+            Rgb::new(1., 1., 1.)
+        } else {
+            shadow_transmittance(scene, &orig, &dir, distance_to_light, medium, time, rng)
+        };
+
+        let area = 2. * PI * sphere.radius * sphere.radius;
+        let geometry = cos_light * area / (PI * distance * distance);
+        f(
+            dir,
+            transmittance * emission.radiance(scene, &normal) * geometry,
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Importance sampling of the env map for direct lighting, with the piecewise constant
+//! distributions of pbrt: https://www.pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Sampling_Light_Sources#InfiniteAreaLights
+
+use std::f32::consts::PI;
+
+use crate::bsdf::sample_cosine_hemisphere;
+use crate::color::Rgb;
+use crate::sampler::Rng;
+use crate::scene::{scene_intersect, EnvMap, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+#[derive(Debug)]
+struct Distribution1D {
+    func: Vec<f32>,
+    /// `func.len() + 1` values from 0 to 1.
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.);
+        for (i, f) in func.iter().enumerate() {
+            cdf.push(cdf[i] + f / n as f32);
+        }
+        let integral = cdf[n];
+        if integral == 0. {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        } else {
+            for c in &mut cdf {
+                *c /= integral;
+            }
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Position in [0, 1) drawn from the distribution, its density and the index of its segment.
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        let index = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let du = if width > 0. {
+            (u - self.cdf[index]) / width
+        } else {
+            0.
+        };
+        let pdf = if self.integral > 0. {
+            self.func[index] / self.integral
+        } else {
+            1.
+        };
+        ((index as f32 + du) / n as f32, pdf, index)
+    }
+
+    fn pdf(&self, x: f32) -> f32 {
+        let index = ((x * self.func.len() as f32) as usize).min(self.func.len() - 1);
+        if self.integral > 0. {
+            self.func[index] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// Env map light, the map texels are weighted by their luminance and solid angle.
+#[derive(Debug)]
+pub struct EnvLight {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+    /// Light samples and BSDF samples taken for every shaded point.
+    pub samples: u32,
+}
+
+impl EnvLight {
+    pub fn new(map: &EnvMap, samples: u32) -> EnvLight {
+        let rows: Vec<Distribution1D> = (0..map.height)
+            .map(|y| {
+                let sin_theta = ((y as f32 + 0.5) / map.height as f32 * PI).sin();
+                let row = &map.pixels[y * map.width..(y + 1) * map.width];
+                Distribution1D::new(row.iter().map(|p| p.luminance() * sin_theta).collect())
+            })
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.integral).collect());
+        EnvLight {
+            rows,
+            marginal,
+            samples,
+        }
+    }
+
+    /// Direction toward the env map and its solid angle density.
+    pub fn sample(&self, u: f32, v: f32) -> Option<(Vec3f32, f32)> {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        let sin_theta = (y * PI).sin();
+        if sin_theta <= 0. || pdf_x * pdf_y <= 0. {
+            return None;
+        }
+        let pdf = pdf_x * pdf_y / (2. * PI * PI * sin_theta);
+        Some((EnvMap::direction(x, y), pdf))
+    }
+
+    pub fn pdf(&self, dir: &Vec3f32) -> f32 {
+        let (x, y) = EnvMap::coordinates(dir);
+        let sin_theta = (y * PI).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x) / (2. * PI * PI * sin_theta)
+    }
+}
+
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    pdf * pdf / (pdf * pdf + other * other)
+}
+
+/// Env map light reaching `p` weighted by the cosine and divided by pi, the counterpart of the
+/// point light intensities of Whitted shading. Light samples and cosine weighted samples are
+/// combined with the power heuristic. The sun of a physical sky is not in the map and is left
+/// to `Sun::lighting`.
+pub fn diffuse_lighting(
+    scene: &Scene,
+    light: &EnvLight,
+    p: &Vec3f32,
+    n: &Vec3f32,
+    time: f32,
+    rng: &mut Rng,
+) -> Rgb {
+    let orig = p + n * 1e-3;
+    let unoccluded = |dir: &Vec3f32| {
+        let mut hit = Vec3f32::new(0., 0., 0.);
+        let mut hit_n = Vec3f32::new(0., 0., 0.);
+        let mut material = Material::default();
+        stats::count(Counter::ShadowRays, 1);
+        !scene_intersect(&orig, dir, time, scene, &mut hit, &mut hit_n, &mut material)
+    };
+
+    let mut sum = Rgb::new(0., 0., 0.);
+    for _ in 0..light.samples {
+        if let Some((dir, pdf_light)) = light.sample(rng.next_f32(), rng.next_f32()) {
+            let cos = dir.dot_product(n);
+            if cos > 0. && unoccluded(&dir) {
+                let pdf_cosine = cos / PI;
+                let weight = power_heuristic(pdf_light, pdf_cosine);
+                sum = sum + scene.background.lookup(&dir) * (cos / PI / pdf_light * weight);
+            }
+        }
+
+        let dir = sample_cosine_hemisphere(n, rng.next_f32(), rng.next_f32());
+        let pdf_cosine = dir.dot_product(n) / PI;
+        if pdf_cosine > 0. && unoccluded(&dir) {
+            let weight = power_heuristic(pdf_cosine, light.pdf(&dir));
+            sum = sum + scene.background.lookup(&dir) * weight;
+        }
+    }
+    sum / light.samples.max(1) as f32
+}
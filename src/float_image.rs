@@ -0,0 +1,401 @@
+//! Floating point image files for linear HDR values: Portable Float Maps and scanline OpenEXR
+//! files with any number of 32 bit float channels. Uncompressed, ZIPS and ZIP EXR files with
+//! half, float or uint channels are read, the other compressions are not.
+//! https://openexr.com/en/latest/OpenEXRFileLayout.html
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::color::Rgb;
+
+/// Writes a color PFM, rows go from the bottom up.
+pub fn write_pfm(path: &str, width: usize, height: usize, pixels: &[Rgb]) -> io::Result<()> {
+    fs::write(path, encode_pfm(width, height, pixels))
+}
+
+/// Bytes of the PFM file of the pixels.
+pub fn encode_pfm(width: usize, height: usize, pixels: &[Rgb]) -> Vec<u8> {
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for c in &[pixel.r, pixel.g, pixel.b] {
+                bytes.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Writes the `channels`, named after the EXR conventions (`R`, `G`, `B` for the main image,
+/// `layer.R` for the layers), each holding `width * height` values in scanline order.
+pub fn write_exr(
+    path: &str,
+    width: usize,
+    height: usize,
+    channels: &[(String, Vec<f32>)],
+) -> io::Result<()> {
+    // Channels are stored in alphabetical order.
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // FLOAT pixels, not linear, reserved, x and y sampling.
+        list.extend_from_slice(&2i32.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &list);
+    write_attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // One scanline per chunk: its y coordinate, its size and the channels one after another.
+    let chunk_size = 8 + 4 * width * channels.len();
+    let first_chunk = header.len() + 8 * height;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+    for y in 0..height {
+        file.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+    for y in 0..height {
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&((chunk_size - 8) as i32).to_le_bytes())?;
+        for (_, values) in &channels {
+            for v in &values[y * width..(y + 1) * width] {
+                file.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+/// Named float channels of an image read from a file, scalar PFMs have a single `Y` channel.
+#[derive(Debug)]
+pub struct FloatImage {
+    pub width: usize,
+    pub height: usize,
+    pub channels: Vec<(String, Vec<f32>)>,
+}
+
+impl FloatImage {
+    pub fn channel(&self, name: &str) -> Option<&[f32]> {
+        self.channels
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, values)| &values[..])
+    }
+
+    /// Colors of `layer` from its `R`, `G` and `B` channels, the empty name is the main image.
+    /// Layers with a single `Y` channel are gray.
+    pub fn layer(&self, layer: &str) -> Option<Vec<Rgb>> {
+        let prefix = if layer.is_empty() {
+            String::new()
+        } else {
+            format!("{}.", layer)
+        };
+        let channel = |c: &str| self.channel(&format!("{}{}", prefix, c));
+        if let (Some(r), Some(g), Some(b)) = (channel("R"), channel("G"), channel("B")) {
+            return Some(
+                (0..self.width * self.height)
+                    .map(|i| Rgb::new(r[i], g[i], b[i]))
+                    .collect(),
+            );
+        }
+        channel("Y").map(|y| y.iter().map(|&v| Rgb::new(v, v, v)).collect())
+    }
+}
+
+/// Reads a PFM or EXR file after its extension.
+pub fn read_float_image(path: &Path) -> Result<FloatImage, String> {
+    let is_exr = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let image = if is_exr {
+        read_exr(&bytes)
+    } else {
+        read_pfm(&bytes)
+    };
+    image.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn read_pfm(bytes: &[u8]) -> Result<FloatImage, String> {
+    // Three whitespace separated header fields after the magic, then a single whitespace.
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while fields.len() < 4 {
+        if i >= bytes.len() {
+            return Err(String::from("truncated PFM header"));
+        }
+        if bytes[i].is_ascii_whitespace() {
+            if i > start {
+                fields.push(String::from_utf8_lossy(&bytes[start..i]).into_owned());
+            }
+            start = i + 1;
+        }
+        i += 1;
+    }
+    let components = match fields[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(String::from("not a PFM file")),
+    };
+    let invalid = |s: &str| format!("invalid PFM value {}", s);
+    let size = |s: &str| s.parse::<usize>().map_err(|_| invalid(s));
+    let (width, height) = (size(&fields[1])?, size(&fields[2])?);
+    let scale = fields[3].parse::<f32>().map_err(|_| invalid(&fields[3]))?;
+    let data_size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4 * components))
+        .ok_or_else(|| String::from("image too large"))?;
+    let data = &bytes[i..];
+    if data.len() < data_size {
+        return Err(String::from("truncated PFM data"));
+    }
+    let value = |k: usize| {
+        let mut b = [0; 4];
+        b.copy_from_slice(&data[4 * k..4 * k + 4]);
+        if scale < 0. {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    };
+    let names: &[&str] = if components == 3 {
+        &["R", "G", "B"]
+    } else {
+        &["Y"]
+    };
+    let channels = names
+        .iter()
+        .enumerate()
+        .map(|(c, name)| {
+            // Rows are stored from the bottom up.
+            let values = (0..width * height)
+                .map(|i| {
+                    let (x, y) = (i % width, height - 1 - i / width);
+                    value((y * width + x) * components + c)
+                })
+                .collect();
+            (name.to_string(), values)
+        })
+        .collect();
+    Ok(FloatImage {
+        width,
+        height,
+        channels,
+    })
+}
+
+// https://en.wikipedia.org/wiki/Half-precision_floating-point_format
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = (h >> 10) & 0x1f;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0. => f32::INFINITY,
+        0x1f => f32::NAN,
+        e => (1. + mantissa / 1024.) * 2f32.powi(e as i32 - 15),
+    }
+}
+
+/// Undoes the predictor and the byte interleaving applied before the deflate compression of
+/// ZIP and ZIPS chunks.
+fn unpredict(data: &mut [u8]) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    let (first, second) = data.split_at(data.len().div_ceil(2));
+    let mut bytes = Vec::with_capacity(data.len());
+    for (i, &b) in first.iter().enumerate() {
+        bytes.push(b);
+        if let Some(&b) = second.get(i) {
+            bytes.push(b);
+        }
+    }
+    bytes
+}
+
+/// Reads scanline EXR files with half, float or uint channels, uncompressed or compressed with
+/// ZIPS or ZIP, the deflate based compressions. The wavelet and lossy compressions of the
+/// other writers (PIZ, PXR24, B44, DWA) are not supported.
+fn read_exr(bytes: &[u8]) -> Result<FloatImage, String> {
+    let truncated = || String::from("truncated EXR file");
+    let read_u32 = |i: usize| -> Result<u32, String> {
+        let b = bytes
+            .get(i..i.checked_add(4).ok_or_else(truncated)?)
+            .ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let read_string = |i: usize| -> Result<(String, usize), String> {
+        let rest = bytes.get(i..).ok_or_else(truncated)?;
+        let end = i + rest.iter().position(|&b| b == 0).ok_or_else(truncated)?;
+        Ok((
+            String::from_utf8_lossy(&bytes[i..end]).into_owned(),
+            end + 1,
+        ))
+    };
+    if bytes.len() < 8 || bytes[..4] != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(String::from("not an EXR file"));
+    }
+    if read_u32(4)? & 0x1600 != 0 {
+        return Err(String::from(
+            "tiled, deep and multi-part EXR files are not supported",
+        ));
+    }
+
+    let mut i = 8;
+    let mut channel_types = Vec::new();
+    let mut window = None;
+    let mut compression = 0;
+    while *bytes.get(i).ok_or_else(truncated)? != 0 {
+        let (name, next) = read_string(i)?;
+        let (_, next) = read_string(next)?;
+        let size = read_u32(next)? as usize;
+        let value = bytes.get(next + 4..next + 4 + size).ok_or_else(truncated)?;
+        match name.as_str() {
+            "channels" => {
+                let mut j = next + 4;
+                while *bytes.get(j).ok_or_else(truncated)? != 0 {
+                    let (channel, after) = read_string(j)?;
+                    channel_types.push((channel, read_u32(after)?));
+                    j = after + 16;
+                }
+            }
+            "compression" => compression = *value.first().ok_or_else(truncated)?,
+            "dataWindow" => {
+                let v: Vec<i32> = (0..4)
+                    .map(|k| read_u32(next + 4 + 4 * k).map(|v| v as i32))
+                    .collect::<Result<_, _>>()?;
+                window = Some((v[0], v[1], v[2], v[3]));
+            }
+            _ => (),
+        }
+        i = next + 4 + size;
+    }
+    // Scanlines per chunk of the no compression, ZIPS and ZIP methods.
+    let chunk_lines = match compression {
+        0 | 2 => 1,
+        3 => 16,
+        other => {
+            return Err(format!(
+                "EXR compression {} is not supported, only none, ZIPS and ZIP are",
+                other
+            ))
+        }
+    };
+    let (x_min, y_min, x_max, y_max) = window.ok_or_else(|| String::from("no data window"))?;
+    let width = (x_max as i64 - x_min as i64 + 1).max(0) as usize;
+    let height = (y_max as i64 - y_min as i64 + 1).max(0) as usize;
+    let sizes: Vec<usize> = channel_types
+        .iter()
+        .map(|(name, kind)| match kind {
+            0 | 2 => Ok(4),
+            1 => Ok(2),
+            _ => Err(format!("channel {} has an unknown pixel type", name)),
+        })
+        .collect::<Result<_, _>>()?;
+    let line_size = width
+        .checked_mul(sizes.iter().sum())
+        .ok_or_else(|| String::from("data window too large"))?;
+    let chunk_count = height.div_ceil(chunk_lines);
+    // A larger window than the file can hold is rejected before the channels are allocated,
+    // deflate shrinks the data 1032 times at most.
+    let max_ratio = if compression == 0 { 1 } else { 1032 };
+    if chunk_count > bytes.len() / 8
+        || line_size.saturating_mul(height) > bytes.len().saturating_mul(max_ratio)
+    {
+        return Err(truncated());
+    }
+
+    let mut channels: Vec<(String, Vec<f32>)> = channel_types
+        .iter()
+        .map(|(name, _)| (name.clone(), vec![0.; width * height]))
+        .collect();
+    let offsets = i + 1;
+    for chunk_index in 0..chunk_count {
+        let lo = read_u32(offsets + 8 * chunk_index)? as u64;
+        let hi = read_u32(offsets + 8 * chunk_index + 4)? as u64;
+        let chunk = usize::try_from(lo | (hi << 32)).map_err(|_| truncated())?;
+        let first_line = (read_u32(chunk)? as i32 as i64 - y_min as i64) as usize;
+        if first_line >= height {
+            return Err(format!("scanline {} out of the data window", first_line));
+        }
+        let lines = chunk_lines.min(height - first_line);
+        let data_size = read_u32(chunk + 4)? as usize;
+        let data = bytes
+            .get(chunk + 8..(chunk + 8).checked_add(data_size).ok_or_else(truncated)?)
+            .ok_or_else(truncated)?;
+        let expected = line_size * lines;
+        // Chunks which deflate would not shrink are stored as they are.
+        let data = if compression == 0 || data.len() >= expected {
+            data.to_vec()
+        } else {
+            let mut inflated = inflate::inflate_bytes_zlib(data)
+                .map_err(|err| format!("invalid ZIP chunk: {}", err))?;
+            unpredict(&mut inflated)
+        };
+        if data.len() < expected {
+            return Err(truncated());
+        }
+
+        let mut p = 0;
+        for y in first_line..first_line + lines {
+            for (((_, values), &size), (_, kind)) in
+                channels.iter_mut().zip(&sizes).zip(&channel_types)
+            {
+                for (x, v) in data[p..p + size * width].chunks(size).enumerate() {
+                    values[y * width + x] = match kind {
+                        0 => u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as f32,
+                        1 => half_to_f32(u16::from_le_bytes([v[0], v[1]])),
+                        _ => f32::from_le_bytes([v[0], v[1], v[2], v[3]]),
+                    };
+                }
+                p += size * width;
+            }
+        }
+    }
+    Ok(FloatImage {
+        width,
+        height,
+        channels,
+    })
+}
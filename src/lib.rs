@@ -0,0 +1,2094 @@
+mod ambient_occlusion;
+pub mod animation;
+mod aov;
+mod bdpt;
+mod bsdf;
+mod bvh;
+pub mod camera;
+mod checkpoint;
+pub mod color;
+mod denoise;
+mod distributed;
+mod emitter;
+mod env_light;
+pub mod float_image;
+mod medium;
+pub mod metrics;
+mod photon_map;
+mod preview;
+mod progress;
+mod sampler;
+pub mod scene;
+mod scene_file;
+mod server;
+mod sky;
+mod spectrum;
+mod stats;
+pub mod tile;
+mod vec3;
+mod watch;
+
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{error, fmt, fs, fs::File, io, io::BufWriter, io::IsTerminal};
+
+use image::{ColorType, ImageError};
+
+use crate::ambient_occlusion::{ambient_occlusion, clay, AoSettings};
+use crate::animation::Animation;
+use crate::aov::{Aov, AovSample, Shading, ALL_AOVS, AOV_COUNT};
+use crate::bdpt::SplatBuffer;
+use crate::camera::{Aperture, Camera, FisheyeMapping, Projection, Stereo, StereoLayout};
+use crate::checkpoint::Checkpoint;
+use crate::color::{false_color, Rgb, Rgba};
+use crate::denoise::{denoise, DenoiseSettings};
+use crate::distributed::{TileResult, Worker};
+use crate::emitter::sample_emitters;
+use crate::env_light::EnvLight;
+use crate::float_image::{read_float_image, write_exr, write_pfm};
+use crate::medium::shadow_transmittance;
+use crate::metrics::Metric;
+use crate::photon_map::{PhotonMaps, PhotonSettings};
+use crate::preview::Preview;
+use crate::progress::Progress;
+use crate::sampler::{PixelState, Rng};
+use crate::scene::{
+    scene_intersect, scene_intersect_object, EnvMap, Light, Material, Scene, Sphere,
+};
+use crate::scene_file::load_scene;
+use crate::sky::{parse_date, parse_time, SkySettings, SunPosition};
+use crate::stats::{Counter, Report};
+use crate::tile::{tiles, Tile, TileOrder};
+use crate::vec3::Vec3f32;
+
+#[derive(Debug)]
+pub enum RayTracerError {
+    Args(String),
+    Parse(ImageError),
+    Scene(String),
+    Image(String),
+    Render(io::Error),
+    /// The render was stopped through `RenderOptions::cancel`.
+    Cancelled,
+}
+
+impl fmt::Display for RayTracerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RayTracerError::Args(ref msg) => write!(f, "Argument error: {}", msg),
+            RayTracerError::Parse(ref err) => write!(f, "Parse error: {}", err),
+            RayTracerError::Scene(ref msg) => write!(f, "Scene error: {}", msg),
+            RayTracerError::Image(ref msg) => write!(f, "Image error: {}", msg),
+            RayTracerError::Render(ref err) => write!(f, "Render error: {}", err),
+            RayTracerError::Cancelled => write!(f, "Render cancelled"),
+        }
+    }
+}
+
+impl error::Error for RayTracerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RayTracerError::Args(_) => None,
+            RayTracerError::Parse(ref err) => Some(err),
+            RayTracerError::Scene(_) => None,
+            RayTracerError::Image(_) => None,
+            RayTracerError::Render(ref err) => Some(err),
+            RayTracerError::Cancelled => None,
+        }
+    }
+}
+
+impl From<io::Error> for RayTracerError {
+    fn from(err: io::Error) -> RayTracerError {
+        RayTracerError::Render(err)
+    }
+}
+
+impl From<ImageError> for RayTracerError {
+    fn from(err: ImageError) -> RayTracerError {
+        RayTracerError::Parse(err)
+    }
+}
+
+// https://doc.rust-lang.org/rust-by-example/error/result/result_alias.html
+// Generic form not needed today type ResultRayTracer<T> = Result<T, RayTracerError>;
+pub type ResultRayTracer = Result<(), RayTracerError>;
+
+fn reflect(i: &Vec3f32, n: &Vec3f32) -> Vec3f32 {
+    i - n * 2. * i.dot_product(n)
+}
+
+fn refract(i: &Vec3f32, n: &Vec3f32, eta_t: f32, eta_i: f32) -> Vec3f32 {
+    let cosi = -i.dot_product(n).clamp(-1., 1.);
+    if cosi < 0. {
+        return refract(i, &(n * -1.), eta_i, eta_t);
+    }
+    let eta = eta_i / eta_t;
+    let k = 1. - eta * eta * (1. - cosi * cosi);
+    if k < 0. {
+        Vec3f32::new(1., 0., 0.)
+    } else {
+        i * eta + n * (eta * cosi - k.sqrt())
+    }
+}
+
+/// Color seen along the ray, which starts in `medium`, an index in `Scene::media`.
+/// `aovs` receives the passes of the surface hit, if any.
+#[allow(clippy::too_many_arguments)]
+fn cast_ray(
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    scene: &Scene,
+    medium: Option<usize>,
+    rng: &mut Rng,
+    depth: usize,
+    aovs: Option<&mut AovSample>,
+) -> Rgb {
+    let mut point = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+
+    let object = scene_intersect_object(orig, dir, time, scene, &mut point, &mut n, &mut material);
+    let hit = object.is_some();
+    let (transmittance, in_scattered) = match medium {
+        Some(index) => {
+            let distance = if hit {
+                (point - orig).norm()
+            } else if medium == scene.fog {
+                scene.fog_distance
+            } else {
+                f32::INFINITY
+            };
+            medium::integrate(scene, index, orig, dir, distance, time, rng)
+        }
+        None => (Rgb::new(1., 1., 1.), Rgb::new(0., 0., 0.)),
+    };
+    if transmittance.r <= 0. && transmittance.g <= 0. && transmittance.b <= 0. {
+        return in_scattered;
+    }
+    let color = match object {
+        Some(object) if depth <= 4 => {
+            stats::record_depth(depth + 1);
+            let shading = shade(dir, time, scene, medium, rng, depth, &point, &n, &material);
+            if let Some(aovs) = aovs {
+                let (object_id, material_id) = object.ids(scene);
+                aov::record_surface(
+                    aovs,
+                    (point - orig).norm(),
+                    &point,
+                    &n,
+                    &material.diffuse_color,
+                    object.uv(&point, &n),
+                    object_id,
+                    material_id,
+                );
+                shading.record(aovs, &transmittance);
+            }
+            shading.total()
+        }
+        _ => scene.environment(dir),
+    };
+    match medium {
+        Some(_) => color * transmittance + in_scattered,
+        None => color,
+    }
+}
+
+/// Whitted shading of the surface hit at `point`, split into its terms.
+#[allow(clippy::too_many_arguments)]
+fn shade(
+    dir: &Vec3f32,
+    time: f32,
+    scene: &Scene,
+    medium: Option<usize>,
+    rng: &mut Rng,
+    depth: usize,
+    point: &Vec3f32,
+    n: &Vec3f32,
+    material: &Material,
+) -> Shading {
+    let (point, n) = (*point, *n);
+    let mut reflect_dir = reflect(dir, &n);
+    reflect_dir.normalize();
+    let mut refract_dir = refract(dir, &n, material.refractive_index, 1.);
+    refract_dir.normalize();
+    let reflect_orig = if reflect_dir.dot_product(&n) < 0. {
+        point - &n * 1e-3
+    } else {
+        point + &n * 1e-3
+    };
+    let refract_orig = if refract_dir.dot_product(&n) < 0. {
+        point - &n * 1e-3
+    } else {
+        point + &n * 1e-3
+    };
+    // Refracted rays entering an object go through the medium inside of it.
+    let refract_medium = if dir.dot_product(&n) < 0. {
+        material.medium
+    } else {
+        scene.fog
+    };
+    let reflect_color = cast_ray(
+        &reflect_orig,
+        &reflect_dir,
+        time,
+        scene,
+        medium,
+        rng,
+        depth + 1,
+        None,
+    );
+    let refract_color = cast_ray(
+        &refract_orig,
+        &refract_dir,
+        time,
+        scene,
+        refract_medium,
+        rng,
+        depth + 1,
+        None,
+    );
+
+    let mut diffuse_light_intensity = Rgb::new(0., 0., 0.);
+    let mut specular_light_intensity = Rgb::new(0., 0., 0.);
+    // Point light diffuse lighting with and without the shadows, for the shadow pass.
+    let mut unshadowed = 0.;
+    let mut shadowed = 0.;
+    for l in &scene.lights {
+        let mut light_dir = l.position - point;
+        let light_distance = light_dir.norm();
+        light_dir.normalize();
+        unshadowed += l.intensity * light_dir.dot_product(&n).max(0.);
+
+        let shadow_orig = if (light_dir * n).norm() < 0. {
+            point - &n * 1e-3
+        } else {
+            point + &n * 1e-3
+        };
+
+        let transmittance = if scene.media.is_empty() {
+            let mut shadow_pt = Vec3f32::new(0., 0., 0.);
+            let mut shadow_n = Vec3f32::new(0., 0., 0.);
+            let mut tmp_material = Material::default();
+            stats::count(Counter::ShadowRays, 1);
+            if scene_intersect(
+                &shadow_orig,
+                &light_dir,
+                time,
+                scene,
+                &mut shadow_pt,
+                &mut shadow_n,
+                &mut tmp_material,
+            ) && (shadow_pt - shadow_orig).norm() < light_distance
+            {
+                continue;
+            }
+            Rgb::new(1., 1., 1.)
+        } else {
+            shadow_transmittance(
+                scene,
+                &shadow_orig,
+                &light_dir,
+                light_distance,
+                medium,
+                time,
+                rng,
+            )
+        };
+
+        diffuse_light_intensity = diffuse_light_intensity
+            + transmittance * (l.intensity * light_dir.dot_product(&n).max(0.));
+        shadowed += transmittance.luminance() * l.intensity * light_dir.dot_product(&n).max(0.);
+        let vec_reflect = reflect(&(light_dir * -1.), &n) * -1.;
+        specular_light_intensity = specular_light_intensity
+            + transmittance
+                * (vec_reflect
+                    .dot_product(dir)
+                    .max(0.)
+                    .powf(material.specular_exponent)
+                    * l.intensity);
+    }
+
+    // Sun and emissive spheres, sampled lights with colored intensities.
+    let mut add_light = |light_dir: Vec3f32, intensity: Rgb| {
+        diffuse_light_intensity =
+            diffuse_light_intensity + intensity * light_dir.dot_product(&n).max(0.);
+        let vec_reflect = reflect(&(light_dir * -1.), &n) * -1.;
+        specular_light_intensity = specular_light_intensity
+            + intensity
+                * vec_reflect
+                    .dot_product(dir)
+                    .max(0.)
+                    .powf(material.specular_exponent);
+    };
+    if let Some(ref sun) = scene.sun {
+        if let Some((sun_dir, intensity)) = sun.lighting(scene, &point, &n, medium, time, rng) {
+            add_light(sun_dir, intensity);
+        }
+    }
+    sample_emitters(scene, &point, &n, medium, time, rng, add_light);
+
+    if let Some(ref env_light) = scene.env_light {
+        if material.albedo.r != 0. {
+            diffuse_light_intensity = diffuse_light_intensity
+                + env_light::diffuse_lighting(scene, env_light, &point, &n, time, rng);
+        }
+    }
+
+    if let Some(ref settings) = scene.ambient_occlusion {
+        if material.albedo.r != 0. {
+            let ao = ambient_occlusion(scene, settings, &point, &n, time, rng);
+            diffuse_light_intensity = diffuse_light_intensity * ao;
+        }
+    }
+
+    Shading {
+        diffuse: material.diffuse_color * diffuse_light_intensity * material.albedo.r,
+        specular: specular_light_intensity * material.albedo.g,
+        reflection: reflect_color * material.albedo.b,
+        refraction: refract_color * material.albedo.a,
+        emission: material.emitted(scene, &n, dir),
+        shadow: if unshadowed > 0. {
+            (1. - shadowed / unshadowed).max(0.)
+        } else {
+            0.
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    Whitted,
+    /// Bidirectional path tracing, see `bdpt.rs`.
+    Bidirectional,
+    /// Photon mapping with caustic and global maps, see `photon_map.rs`.
+    PhotonMapping,
+    /// Clay render shaded by ambient occlusion only.
+    AmbientOcclusion,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    width: usize,
+    height: usize,
+    camera: Camera,
+    /// Pixel whose visible object the camera focuses on.
+    autofocus: Option<(f32, f32)>,
+    /// Field of view and f-stop overriding the ones of the scene file.
+    fov: Option<f32>,
+    f_stop: Option<f32>,
+    min_samples: u32,
+    max_samples: u32,
+    /// A pixel stops being sampled once its relative error falls below this value.
+    threshold: f32,
+    integrator: Integrator,
+    /// Maximum number of bounces of the physically based integrators.
+    max_depth: usize,
+    /// Env map light samples per shaded point, the env map only lights the scene when set.
+    env_samples: Option<u32>,
+    /// Traces sampled wavelengths instead of RGB, Whitted integrator only.
+    spectral: bool,
+    photons: PhotonSettings,
+    ao: AoSettings,
+    /// Multiplies ambient occlusion into the Whitted shading.
+    ao_shading: bool,
+    /// Physical sky replacing the env map of the scene.
+    sky: Option<SkySettings>,
+    stereo: Option<Stereo>,
+    scene: Option<String>,
+    /// Inclusive range of animation frames written to numbered files.
+    frames: Option<(i32, i32)>,
+    output: String,
+    heatmap: Option<String>,
+    aovs: Vec<Aov>,
+    /// Where the AOVs go, next to the image by default.
+    aov_output: Option<String>,
+    /// Filters the image guided by its albedo and normals before it is written.
+    denoise: Option<DenoiseSettings>,
+    tile_size: usize,
+    tile_order: TileOrder,
+    /// Draws a progress bar when the standard error is a terminal.
+    progress: bool,
+    /// Draws the image in the terminal as it renders.
+    preview: bool,
+    /// Renders the scene file again at `watch_scale` times the resolution whenever it or the
+    /// files it loads change.
+    watch: bool,
+    watch_scale: f32,
+    /// Where the statistics report is also written as JSON.
+    stats_json: Option<String>,
+    /// Samples per pixel added to the whole image at a time, all at once by default.
+    round_samples: Option<u32>,
+    /// Doubles the samples per pixel every round instead, 1, 2, 4 and so on, for the images of
+    /// the watch mode.
+    doubling_rounds: bool,
+    /// Where the state of the render is saved, at most every `checkpoint_interval` seconds.
+    checkpoint: Option<String>,
+    checkpoint_interval: f32,
+    /// Goes on from the checkpoint if there is one.
+    resume: bool,
+    /// Address the tiles are handed out on to worker processes instead of rendered here.
+    listen: Option<String>,
+    /// Seconds without an answer after which a worker is dropped and its tiles handed out
+    /// again.
+    worker_timeout: f32,
+    /// Set from another thread to stop the render after the tiles in progress.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            width: 1024,
+            height: 728,
+            camera: Camera::new(
+                Vec3f32::new(0., 0., 0.),
+                (std::f64::consts::PI / 2.0) as f32,
+            ),
+            autofocus: None,
+            fov: None,
+            f_stop: None,
+            min_samples: 1,
+            max_samples: 1,
+            threshold: 0.,
+            integrator: Integrator::Whitted,
+            max_depth: 5,
+            env_samples: None,
+            spectral: false,
+            photons: PhotonSettings::default(),
+            ao: AoSettings::default(),
+            ao_shading: false,
+            sky: None,
+            stereo: None,
+            scene: None,
+            frames: None,
+            output: String::from("out.ppm"),
+            heatmap: None,
+            aovs: Vec::new(),
+            aov_output: None,
+            denoise: None,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            progress: true,
+            preview: false,
+            watch: false,
+            watch_scale: 0.25,
+            stats_json: None,
+            round_samples: None,
+            doubling_rounds: false,
+            checkpoint: None,
+            checkpoint_interval: 300.,
+            resume: false,
+            listen: None,
+            worker_timeout: 600.,
+            cancel: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    fn cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// Samples per pixel between two checkpoints.
+const CHECKPOINT_ROUND_SAMPLES: u32 = 16;
+
+/// Samples a pixel takes before `--threshold` can stop it, when `--min-spp` is not given.
+const ADAPTIVE_MIN_SAMPLES: u32 = 4;
+
+const DEFAULT_STEREO: Stereo = Stereo {
+    layout: StereoLayout::SideBySide,
+    interocular: 0.065,
+    convergence: 10.,
+};
+
+pub fn parse_args(args: &[String]) -> Result<RenderOptions, RayTracerError> {
+    let mut options = RenderOptions::default();
+    let mut min_samples = None;
+    let mut location = None;
+    let (mut date, mut hours, mut utc_offset) = ((2024, 6, 21), 12., 0.);
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| RayTracerError::Args(format!("missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--spp" => options.max_samples = parse_value(&value()?)?,
+            "--min-spp" => min_samples = Some(parse_value(&value()?)?),
+            "--threshold" => options.threshold = parse_value(&value()?)?,
+            "--heatmap" => options.heatmap = Some(value()?),
+            "--aovs" => {
+                let value = value()?;
+                options.aovs = if value == "all" {
+                    ALL_AOVS.to_vec()
+                } else {
+                    value
+                        .split(',')
+                        .map(|name| {
+                            Aov::from_name(name).ok_or_else(|| {
+                                RayTracerError::Args(format!("unknown AOV {}", name))
+                            })
+                        })
+                        .collect::<Result<_, _>>()?
+                };
+            }
+            "--aov-output" => options.aov_output = Some(value()?),
+            "--denoise" => {
+                options.denoise.get_or_insert_with(DenoiseSettings::default);
+            }
+            "--denoise-iterations" => {
+                options
+                    .denoise
+                    .get_or_insert_with(DenoiseSettings::default)
+                    .iterations = parse_value(&value()?)?
+            }
+            "--integrator" => {
+                options.integrator = match value()?.as_str() {
+                    "whitted" => Integrator::Whitted,
+                    "bdpt" => Integrator::Bidirectional,
+                    "photon" => Integrator::PhotonMapping,
+                    "ao" => Integrator::AmbientOcclusion,
+                    other => {
+                        return Err(RayTracerError::Args(format!(
+                            "unknown integrator {}",
+                            other
+                        )))
+                    }
+                }
+            }
+            "--ao-samples" => options.ao.samples = parse_value(&value()?)?,
+            "--ao-distance" => options.ao.max_distance = parse_value(&value()?)?,
+            "--ao-shading" => options.ao_shading = true,
+            "--env-samples" => options.env_samples = Some(parse_value(&value()?)?),
+            "--spectral" => options.spectral = true,
+            "--max-depth" => options.max_depth = parse_value(&value()?)?,
+            "--photons" => options.photons.global_photons = parse_value(&value()?)?,
+            "--caustic-photons" => options.photons.caustic_photons = parse_value(&value()?)?,
+            "--photon-radius" => options.photons.radius = parse_value(&value()?)?,
+            "--final-gather" => options.photons.final_gather = parse_value(&value()?)?,
+            "--progressive" => options.photons.passes = Some(parse_value(&value()?)?),
+            "--sky" => {
+                options.sky.get_or_insert_with(SkySettings::default);
+            }
+            "--turbidity" => {
+                options
+                    .sky
+                    .get_or_insert_with(SkySettings::default)
+                    .turbidity = parse_value(&value()?)?
+            }
+            "--ground-albedo" => {
+                let albedo = parse_vec3(&value()?)?;
+                options
+                    .sky
+                    .get_or_insert_with(SkySettings::default)
+                    .ground_albedo = Rgb::new(albedo.x, albedo.y, albedo.z);
+            }
+            "--sun" => {
+                let (azimuth, elevation) = parse_pair(&value()?)?;
+                options.sky.get_or_insert_with(SkySettings::default).sun =
+                    SunPosition::Angles { azimuth, elevation };
+            }
+            "--sun-location" => location = Some(parse_pair(&value()?)?),
+            "--date" => date = parse_date(&value()?).map_err(RayTracerError::Args)?,
+            "--time" => hours = parse_time(&value()?).map_err(RayTracerError::Args)?,
+            "--utc-offset" => utc_offset = parse_value(&value()?)?,
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--checkpoint-interval" => options.checkpoint_interval = parse_value(&value()?)?,
+            "--resume" => options.resume = true,
+            "--preview" => options.preview = true,
+            "--watch" => options.watch = true,
+            "--watch-scale" => options.watch_scale = parse_value(&value()?)?,
+            "--no-progress" => options.progress = false,
+            "--stats-json" => options.stats_json = Some(value()?),
+            "--tile-size" => options.tile_size = parse_value(&value()?)?,
+            "--tile-order" => {
+                let name = value()?;
+                options.tile_order = TileOrder::from_name(&name)
+                    .ok_or_else(|| RayTracerError::Args(format!("unknown tile order {}", name)))?;
+            }
+            "--width" => options.width = parse_value(&value()?)?,
+            "--height" => options.height = parse_value(&value()?)?,
+            "--fov" => options.fov = Some(parse_value::<f32>(&value()?)?.to_radians()),
+            "--projection" => {
+                options.camera.projection = match value()?.as_str() {
+                    "perspective" => Projection::Perspective,
+                    "orthographic" => Projection::Orthographic { height: 10. },
+                    "fisheye-equidistant" => Projection::Fisheye(FisheyeMapping::Equidistant),
+                    "fisheye-equisolid" => Projection::Fisheye(FisheyeMapping::Equisolid),
+                    "equirectangular" => Projection::Equirectangular,
+                    "cubemap" => Projection::Cubemap,
+                    other => {
+                        return Err(RayTracerError::Args(format!(
+                            "unknown projection {}",
+                            other
+                        )))
+                    }
+                }
+            }
+            "--ortho-height" => {
+                options.camera.projection = Projection::Orthographic {
+                    height: parse_value(&value()?)?,
+                }
+            }
+            "--aperture" => options.camera.aperture_radius = parse_value(&value()?)?,
+            "--f-stop" => options.f_stop = Some(parse_value(&value()?)?),
+            "--focus-distance" => options.camera.focus_distance = parse_value(&value()?)?,
+            "--autofocus" => options.autofocus = Some(parse_pair(&value()?)?),
+            "--shutter" => {
+                let (open, close) = parse_pair(&value()?)?;
+                options.camera.shutter_open = open;
+                options.camera.shutter_close = close;
+            }
+            "--camera-velocity" => options.camera.velocity = parse_vec3(&value()?)?,
+            "--stereo" => {
+                let layout = match value()?.as_str() {
+                    "side-by-side" => StereoLayout::SideBySide,
+                    "top-bottom" => StereoLayout::TopBottom,
+                    "anaglyph" => StereoLayout::Anaglyph,
+                    other => return Err(RayTracerError::Args(format!("unknown layout {}", other))),
+                };
+                let stereo = options.stereo.get_or_insert(DEFAULT_STEREO);
+                stereo.layout = layout;
+            }
+            "--interocular" => {
+                options.stereo.get_or_insert(DEFAULT_STEREO).interocular = parse_value(&value()?)?
+            }
+            "--convergence" => {
+                options.stereo.get_or_insert(DEFAULT_STEREO).convergence = parse_value(&value()?)?
+            }
+            "--blades" => {
+                options.camera.aperture = Aperture::Polygon {
+                    blades: parse_value(&value()?)?,
+                    rotation: 0.,
+                }
+            }
+            "--scene" => options.scene = Some(value()?),
+            "--frames" => {
+                let range = value()?;
+                let (first, last) = range.split_once("..").ok_or_else(|| {
+                    RayTracerError::Args(format!("expected first..last, got {}", range))
+                })?;
+                let (first, last) = (parse_value(first)?, parse_value(last)?);
+                if first > last {
+                    return Err(RayTracerError::Args(format!(
+                        "the frame range {} ends before it starts",
+                        range
+                    )));
+                }
+                options.frames = Some((first, last));
+            }
+            "--listen" => options.listen = Some(value()?),
+            "--worker-timeout" => options.worker_timeout = parse_value(&value()?)?,
+            "-o" | "--output" => options.output = value()?,
+            _ => return Err(RayTracerError::Args(format!("unknown argument {}", arg))),
+        }
+    }
+    if !options.aovs.is_empty() && options.stereo.is_some() {
+        return Err(RayTracerError::Args(String::from(
+            "AOVs are not written in stereo",
+        )));
+    }
+    if options.width == 0 || options.height == 0 {
+        return Err(RayTracerError::Args(String::from(
+            "the image needs a width and a height of at least 1",
+        )));
+    }
+    check_image_path(&options.output)?;
+    for path in options.heatmap.iter().chain(&options.aov_output) {
+        check_image_path(path)?;
+    }
+    if options.resume && options.checkpoint.is_none() {
+        return Err(RayTracerError::Args(String::from(
+            "--resume needs a --checkpoint file",
+        )));
+    }
+    let single_image = options.stereo.is_none() && options.frames.is_none();
+    if options.listen.is_some() && (!single_image || options.checkpoint.is_some()) {
+        return Err(RayTracerError::Args(String::from(
+            "--listen renders a single image, without stereo or checkpoints",
+        )));
+    }
+    if options.watch && options.scene.is_none() {
+        return Err(RayTracerError::Args(String::from(
+            "--watch needs a --scene file",
+        )));
+    }
+    if options.watch && (!single_image || options.listen.is_some() || options.checkpoint.is_some())
+    {
+        return Err(RayTracerError::Args(String::from(
+            "--watch renders a single image locally, without stereo, frames, workers or checkpoints",
+        )));
+    }
+    if options.checkpoint.is_some() {
+        options
+            .round_samples
+            .get_or_insert(CHECKPOINT_ROUND_SAMPLES);
+    }
+    if options.spectral && options.integrator != Integrator::Whitted {
+        return Err(RayTracerError::Args(String::from(
+            "spectral mode needs the whitted integrator",
+        )));
+    }
+    // Only the RGB whitted shading samples the env map light.
+    if options.env_samples.is_some()
+        && (options.spectral || options.integrator != Integrator::Whitted)
+    {
+        return Err(RayTracerError::Args(String::from(
+            "--env-samples needs the whitted integrator without spectral mode",
+        )));
+    }
+    if let Some((latitude, longitude)) = location {
+        options.sky.get_or_insert_with(SkySettings::default).sun = SunPosition::Location {
+            latitude,
+            longitude,
+            date,
+            hours,
+            utc_offset,
+        };
+    }
+    options.max_samples = options.max_samples.max(1);
+    let default_min_samples = if options.threshold > 0. {
+        ADAPTIVE_MIN_SAMPLES
+    } else {
+        options.max_samples
+    };
+    options.min_samples = min_samples
+        .unwrap_or(default_min_samples)
+        .clamp(1, options.max_samples);
+    Ok(options)
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> Result<T, RayTracerError> {
+    value
+        .parse()
+        .map_err(|_| RayTracerError::Args(format!("invalid value {}", value)))
+}
+
+fn parse_pair(value: &str) -> Result<(f32, f32), RayTracerError> {
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [a, b] => Ok((parse_value(a)?, parse_value(b)?)),
+        _ => Err(RayTracerError::Args(format!("expected a,b, got {}", value))),
+    }
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3f32, RayTracerError> {
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [x, y, z] => Ok(Vec3f32::new(
+            parse_value(x)?,
+            parse_value(y)?,
+            parse_value(z)?,
+        )),
+        _ => Err(RayTracerError::Args(format!(
+            "expected x,y,z, got {}",
+            value
+        ))),
+    }
+}
+
+/// Numbered file name for `frame`, a run of `#` in `path` is replaced by the zero padded
+/// frame number, otherwise the number is appended to the file stem.
+fn frame_path(path: &str, frame: i32) -> String {
+    if let Some(start) = path.find('#') {
+        let width = path[start..].chars().take_while(|&c| c == '#').count();
+        let number = format!("{:0width$}", frame, width = width);
+        return format!("{}{}{}", &path[..start], number, &path[start + width..]);
+    }
+    match path.rfind('.') {
+        Some(dot) => format!("{}_{:04}{}", &path[..dot], frame, &path[dot..]),
+        None => format!("{}_{:04}", path, frame),
+    }
+}
+
+fn write_ppm(path: &str, width: usize, height: usize, pixels: &[Rgb]) -> ResultRayTracer {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(&mut file, "P6\n{} {}\n255\n", width, height)?;
+    file.write_all(&to_rgb8(pixels))?;
+    Ok(())
+}
+
+/// 8-bit values of the pixels, see `Rgb::to_display`.
+fn to_rgb8(pixels: &[Rgb]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|pixel| {
+            let v = pixel.to_display();
+            [(255. * v.r) as u8, (255. * v.g) as u8, (255. * v.b) as u8]
+        })
+        .collect()
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+fn rgb_channels(prefix: &str, pixels: &[Rgb]) -> Vec<(String, Vec<f32>)> {
+    vec![
+        (format!("{}R", prefix), pixels.iter().map(|p| p.r).collect()),
+        (format!("{}G", prefix), pixels.iter().map(|p| p.g).collect()),
+        (format!("{}B", prefix), pixels.iter().map(|p| p.b).collect()),
+    ]
+}
+
+/// Extensions of the images written, the first ones with the linear values, the others with
+/// 8-bit values.
+const IMAGE_EXTENSIONS: [&str; 6] = ["pfm", "exr", "ppm", "png", "jpg", "jpeg"];
+
+fn check_image_path(path: &str) -> ResultRayTracer {
+    if IMAGE_EXTENSIONS.iter().any(|e| has_extension(path, e)) {
+        Ok(())
+    } else {
+        Err(RayTracerError::Args(format!(
+            "{}: unknown image format, expected one of .{}",
+            path,
+            IMAGE_EXTENSIONS.join(", .")
+        )))
+    }
+}
+
+/// Writes a PFM or EXR file with the linear values, or a PPM, PNG or JPEG file, after the
+/// extension of `path`.
+fn write_image(path: &str, width: usize, height: usize, pixels: &[Rgb]) -> ResultRayTracer {
+    check_image_path(path)?;
+    if has_extension(path, "pfm") {
+        write_pfm(path, width, height, pixels)?;
+    } else if has_extension(path, "exr") {
+        write_exr(path, width, height, &rgb_channels("", pixels))?;
+    } else if has_extension(path, "ppm") {
+        write_ppm(path, width, height, pixels)?;
+    } else {
+        let (width, height) = (width as u32, height as u32);
+        image::save_buffer(path, &to_rgb8(pixels), width, height, ColorType::RGB(8))?;
+    }
+    Ok(())
+}
+
+/// `path` with `suffix` appended to its file stem.
+fn suffixed_path(path: &str, suffix: &str) -> String {
+    match path.rfind('.') {
+        Some(dot) => format!("{}_{}{}", &path[..dot], suffix, &path[dot..]),
+        None => format!("{}_{}", path, suffix),
+    }
+}
+
+/// Writes the requested AOVs, `AOV_COUNT` values per pixel in `aovs`, as layers of a
+/// multi-layer EXR next to the image when `path` is an EXR file, otherwise as separate images
+/// named after `path` with the AOV name appended to the file stem.
+fn write_aovs(
+    path: &str,
+    options: &RenderOptions,
+    framebuffer: &[Rgb],
+    aovs: &[Rgb],
+) -> ResultRayTracer {
+    let (width, height) = (options.width, options.height);
+    let values = |aov: Aov| -> Vec<Rgb> {
+        aovs.chunks(AOV_COUNT)
+            .map(|pixel| pixel[aov as usize])
+            .collect()
+    };
+    if has_extension(path, "exr") {
+        let mut channels = rgb_channels("", framebuffer);
+        for &aov in &options.aovs {
+            let pixels = values(aov);
+            for (c, suffix) in aov.channels().iter().enumerate() {
+                let component = pixels.iter().map(|p| [p.r, p.g, p.b][c]).collect();
+                channels.push((format!("{}.{}", aov.name(), suffix), component));
+            }
+        }
+        write_exr(path, width, height, &channels)?;
+        return Ok(());
+    }
+    for &aov in &options.aovs {
+        let aov_path = suffixed_path(path, aov.name());
+        let pixels = if has_extension(path, "pfm") {
+            values(aov)
+        } else {
+            aov.display(&values(aov))
+        };
+        write_image(&aov_path, width, height, &pixels)?;
+    }
+    Ok(())
+}
+
+/// Distance along the view axis of the object seen through the center of pixel (`x`, `y`).
+fn autofocus(
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    x: f32,
+    y: f32,
+    scene: &Scene,
+) -> Option<f32> {
+    let (offset, dir) = camera.projection_ray(width, height, x + 0.5, y + 0.5)?;
+    let time = camera.shutter_open;
+    let orig = camera.position_at(time) + offset;
+    let mut hit = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+    if scene_intersect(&orig, &dir, time, scene, &mut hit, &mut n, &mut material) {
+        Some(orig.z - hit.z)
+    } else {
+        None
+    }
+}
+
+/// Samples the pixel `index` until it has `sample_limit` samples or its estimated error is
+/// low enough, `state` keeps its statistics and random sequence between two calls. `aovs`
+/// receives the sums of the passes of the samples.
+#[allow(clippy::too_many_arguments)]
+fn render_pixel(
+    scene: &Scene,
+    options: &RenderOptions,
+    camera: &Camera,
+    photons: Option<&PhotonMaps>,
+    splats: &SplatBuffer,
+    index: usize,
+    state: &mut PixelState,
+    sample_limit: u32,
+    mut aovs: Option<&mut [Rgb]>,
+) {
+    let (width, height) = (options.width, options.height);
+    let black = Rgb::new(0., 0., 0.);
+    let i = (index % width) as f32;
+    let j = (index / width) as f32;
+    let PixelState { rng, stats } = state;
+    while stats.count() < sample_limit.min(options.max_samples) {
+        if stats.count() >= options.min_samples && stats.relative_error() < options.threshold {
+            break;
+        }
+        // A single sample goes through the pixel center, more are jittered.
+        let (jitter_x, jitter_y) = if options.max_samples == 1 {
+            (0.5, 0.5)
+        } else {
+            (rng.next_f32(), rng.next_f32())
+        };
+        let lens = [rng.next_f32(), rng.next_f32(), rng.next_f32()];
+        let time = camera.sample_time(rng.next_f32());
+        let ray = camera.generate_ray(width, height, i + jitter_x, j + jitter_y, lens, time);
+        let mut sample_aovs = aov::background();
+        if ray.is_some() {
+            stats::count(Counter::PrimaryRays, 1);
+        }
+        if let (Some((orig, dir)), Some(_)) = (ray, aovs.as_ref()) {
+            // The Whitted integrator records its AOVs while shading.
+            if options.integrator != Integrator::Whitted || options.spectral {
+                aov::record_primary(&mut sample_aovs, scene, &orig, &dir, time);
+            }
+        }
+        stats.add(match ray {
+            Some((orig, dir)) => match options.integrator {
+                Integrator::Whitted if options.spectral => {
+                    spectrum::trace(&orig, &dir, time, scene, rng)
+                }
+                Integrator::Whitted => {
+                    let sample_aovs = aovs.as_ref().map(|_| &mut sample_aovs);
+                    cast_ray(&orig, &dir, time, scene, scene.fog, rng, 0, sample_aovs)
+                }
+                Integrator::Bidirectional => bdpt::sample(
+                    scene,
+                    camera,
+                    width,
+                    height,
+                    &orig,
+                    &dir,
+                    time,
+                    rng,
+                    options.max_depth,
+                    splats,
+                ),
+                Integrator::PhotonMapping => photons.map_or(black, |maps| {
+                    maps.radiance(scene, &orig, &dir, time, rng, 0, options.max_depth)
+                }),
+                Integrator::AmbientOcclusion => clay(scene, &options.ao, &orig, &dir, time, rng),
+            },
+            None => black,
+        });
+        stats::end_path();
+        if let Some(ref mut aovs) = aovs {
+            for (k, aov) in ALL_AOVS.iter().enumerate() {
+                if aov.is_filtered() {
+                    aovs[k] = aovs[k] + sample_aovs[k];
+                } else if stats.count() == 1 {
+                    aovs[k] = sample_aovs[k];
+                }
+            }
+        }
+    }
+}
+
+/// Colors, sample counts and AOVs of the pixels of an image.
+pub type Rendered = (Vec<Rgb>, Vec<u32>, Vec<Rgb>);
+
+/// Sampling state of every pixel during one pass over the image.
+struct PassState {
+    pixels: Vec<PixelState>,
+    /// Sums of the AOV samples, `AOV_COUNT` values per pixel, only recorded when some AOVs
+    /// are requested or the denoiser needs them.
+    aovs: Vec<Rgb>,
+    splats: SplatBuffer,
+}
+
+impl PassState {
+    /// `pass` selects the random sequences of the pixels.
+    fn new(options: &RenderOptions, pass: u32) -> PassState {
+        let pixel_count = options.width * options.height;
+        let record_aovs = !options.aovs.is_empty() || options.denoise.is_some();
+        PassState {
+            pixels: (0..pixel_count)
+                .map(|index| PixelState::new((pass as usize * pixel_count + index) as u64))
+                .collect(),
+            aovs: if record_aovs {
+                vec![Rgb::new(0., 0., 0.); pixel_count * AOV_COUNT]
+            } else {
+                Vec::new()
+            },
+            splats: SplatBuffer::new(options.width, options.height),
+        }
+    }
+
+    /// Returns the colors, the sample counts and the AOVs of the pixels.
+    fn resolve(&self) -> Rendered {
+        let mut framebuffer: Vec<Rgb> = self.pixels.iter().map(|p| p.stats.mean()).collect();
+        let sample_counts: Vec<u32> = self.pixels.iter().map(|p| p.stats.count()).collect();
+        let mut aovs = self.aovs.clone();
+        for (pixel, &count) in aovs.chunks_mut(AOV_COUNT).zip(&sample_counts) {
+            average_aovs(pixel, count);
+        }
+
+        // Every camera sample also traced one light subpath.
+        let light_paths = sample_counts.iter().map(|&count| count as u64).sum();
+        self.splats.resolve(&mut framebuffer, light_paths);
+
+        (framebuffer, sample_counts, aovs)
+    }
+}
+
+/// Divides the sums of the filtered AOVs of a pixel by its number of samples.
+fn average_aovs(pixel: &mut [Rgb], count: u32) {
+    for (k, aov) in ALL_AOVS.iter().enumerate() {
+        if aov.is_filtered() {
+            pixel[k] = pixel[k] / count.max(1) as f32;
+        }
+    }
+}
+
+/// Samples every pixel of `state` up to `sample_limit` samples. The tiles are handed out to
+/// the threads in the order of the options and given to `on_tile` with the current colors of
+/// their pixels once done.
+fn render_round(
+    scene: &Scene,
+    options: &RenderOptions,
+    camera: &Camera,
+    photons: Option<&PhotonMaps>,
+    state: &mut PassState,
+    sample_limit: u32,
+    on_tile: &(dyn Fn(&Tile, &[Rgb]) + Sync),
+) {
+    let width = options.width;
+    let record_aovs = !state.aovs.is_empty();
+    let tiles = tiles(width, options.height, options.tile_size, options.tile_order);
+    let next_tile = AtomicUsize::new(0);
+    let splats = &state.splats;
+    let output = Mutex::new((&mut state.pixels, &mut state.aovs));
+    rayon::scope(|s| {
+        for _ in 0..rayon::current_num_threads() {
+            s.spawn(|_| {
+                while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    if options.cancelled() {
+                        break;
+                    }
+                    let (mut pixels, mut tile_aovs) = {
+                        let (pixels, aovs) = &*output.lock().unwrap();
+                        let tile_pixels: Vec<PixelState> =
+                            tile.pixels(width).map(|index| pixels[index]).collect();
+                        let tile_aovs: Vec<Rgb> = if record_aovs {
+                            tile.pixels(width)
+                                .flat_map(|index| {
+                                    aovs[index * AOV_COUNT..(index + 1) * AOV_COUNT].to_vec()
+                                })
+                                .collect()
+                        } else {
+                            Vec::new()
+                        };
+                        (tile_pixels, tile_aovs)
+                    };
+                    let mut aov_chunks = tile_aovs.chunks_mut(AOV_COUNT);
+                    for (index, pixel) in tile.pixels(width).zip(pixels.iter_mut()) {
+                        render_pixel(
+                            scene,
+                            options,
+                            camera,
+                            photons,
+                            splats,
+                            index,
+                            pixel,
+                            sample_limit,
+                            aov_chunks.next(),
+                        );
+                    }
+
+                    {
+                        let (output_pixels, aovs) = &mut *output.lock().unwrap();
+                        for (k, index) in tile.pixels(width).enumerate() {
+                            output_pixels[index] = pixels[k];
+                            if record_aovs {
+                                aovs[index * AOV_COUNT..(index + 1) * AOV_COUNT].copy_from_slice(
+                                    &tile_aovs[k * AOV_COUNT..(k + 1) * AOV_COUNT],
+                                );
+                            }
+                        }
+                    }
+                    let colors: Vec<Rgb> = pixels.iter().map(|p| p.stats.mean()).collect();
+                    on_tile(tile, &colors);
+                }
+            });
+        }
+    });
+}
+
+/// Takes every sample of the pixels of `tile` in `pass`, the unit of work of distributed
+/// renders. `splats` is cleared of the light splatted by other tiles.
+fn render_tile(
+    scene: &Scene,
+    options: &RenderOptions,
+    photons: Option<&PhotonMaps>,
+    splats: &SplatBuffer,
+    pass: u32,
+    tile: &Tile,
+) -> TileResult {
+    let pixel_count = options.width * options.height;
+    let record_aovs = !options.aovs.is_empty() || options.denoise.is_some();
+    let mut result = TileResult {
+        pass,
+        tile: *tile,
+        colors: Vec::new(),
+        sample_counts: Vec::new(),
+        aovs: Vec::new(),
+        splats: Vec::new(),
+    };
+    splats.take_sparse();
+    for index in tile.pixels(options.width) {
+        let mut state = PixelState::new((pass as usize * pixel_count + index) as u64);
+        let mut aovs = [Rgb::new(0., 0., 0.); AOV_COUNT];
+        render_pixel(
+            scene,
+            options,
+            &options.camera,
+            photons,
+            splats,
+            index,
+            &mut state,
+            options.max_samples,
+            if record_aovs { Some(&mut aovs) } else { None },
+        );
+        result.colors.push(state.stats.mean());
+        result.sample_counts.push(state.stats.count());
+        if record_aovs {
+            average_aovs(&mut aovs, state.stats.count());
+            result.aovs.extend_from_slice(&aovs);
+        }
+    }
+    result.splats = splats.take_sparse();
+    result
+}
+
+/// Number of passes over the image, progressive photon mapping only traces more than one.
+fn pass_count(options: &RenderOptions) -> u32 {
+    match options.integrator {
+        Integrator::PhotonMapping => options.photons.passes.unwrap_or(1).max(1),
+        _ => 1,
+    }
+}
+
+/// Samples per pixel of a pass after the round following the one ending at `sample_limit`.
+fn next_round_limit(options: &RenderOptions, sample_limit: u32) -> u32 {
+    let next = if options.doubling_rounds {
+        (2 * sample_limit).max(1)
+    } else {
+        sample_limit + options.round_samples.unwrap_or(options.max_samples).max(1)
+    };
+    next.min(options.max_samples)
+}
+
+/// Rounds of samples of every pass.
+fn round_count(options: &RenderOptions) -> u32 {
+    let mut rounds = 0;
+    let mut sample_limit = 0;
+    while sample_limit < options.max_samples {
+        sample_limit = next_round_limit(options, sample_limit);
+        rounds += 1;
+    }
+    rounds
+}
+
+/// Photons lighting `pass`, for the photon mapping integrator.
+fn pass_photons(
+    scene: &Scene,
+    options: &RenderOptions,
+    camera: &Camera,
+    pass: u32,
+) -> Option<PhotonMaps> {
+    match options.integrator {
+        Integrator::PhotonMapping => Some(stats::time_phase("photons", || {
+            PhotonMaps::emit(
+                scene,
+                camera,
+                &options.photons,
+                options.photons.pass_radius(pass),
+                options.max_depth,
+                pass as u64,
+            )
+        })),
+        _ => None,
+    }
+}
+
+/// Adds the colors and sample counts of `pass` to the average of the previous passes.
+fn accumulate_pass(
+    framebuffer: &mut [Rgb],
+    sample_counts: &mut [u32],
+    pass: u32,
+    pixels: Vec<Rgb>,
+    counts: Vec<u32>,
+) {
+    for (v, pixel) in framebuffer.iter_mut().zip(pixels) {
+        *v = *v + (pixel - *v) / (pass + 1) as f32;
+    }
+    for (count, pass_count) in sample_counts.iter_mut().zip(counts) {
+        *count += pass_count;
+    }
+}
+
+/// Filters `framebuffer` guided by the albedo and normals among `aovs`.
+fn denoise_image(
+    options: &RenderOptions,
+    settings: &DenoiseSettings,
+    framebuffer: &[Rgb],
+    aovs: &[Rgb],
+) -> Vec<Rgb> {
+    let layer = |aov: Aov| -> Vec<Rgb> {
+        aovs.chunks(AOV_COUNT)
+            .map(|pixel| pixel[aov as usize])
+            .collect()
+    };
+    let (albedo, normal) = (layer(Aov::Albedo), layer(Aov::Normal));
+    stats::time_phase("denoise", || {
+        denoise(
+            options.width,
+            options.height,
+            framebuffer,
+            &albedo,
+            &normal,
+            settings,
+        )
+    })
+}
+
+/// Settings a checkpoint of the image seen by `camera` depends on.
+fn checkpoint_fingerprint(options: &RenderOptions, camera: &Camera) -> String {
+    format!(
+        "{:?} {:?} {}x{} {:?} {} {} {} {:?} {} {:?} {:?} {:?} {} {:?} {} {}",
+        options.scene,
+        camera,
+        options.width,
+        options.height,
+        options.integrator,
+        options.min_samples,
+        options.max_samples,
+        options.threshold,
+        options.env_samples,
+        options.spectral,
+        options.photons,
+        options.ao,
+        options.sky,
+        options.ao_shading,
+        options.aovs,
+        options.denoise.is_some(),
+        options.max_depth,
+    )
+}
+
+/// Returns the image seen by `camera`, denoised if requested, and the number of samples taken
+/// for each pixel. The AOVs are the ones of the last pass.
+///
+/// With a `checkpoint` path, the state of the render is written there between two rounds
+/// when the interval of the options has passed, and read back first to resume.
+pub fn render_image(
+    scene: &Scene,
+    options: &RenderOptions,
+    camera: &Camera,
+    checkpoint: Option<&str>,
+    on_tile: &(dyn Fn(&Tile, &[Rgb]) + Sync),
+) -> Result<Rendered, RayTracerError> {
+    render_image_rounds(scene, options, camera, checkpoint, on_tile, None)
+}
+
+/// `render_image` giving `on_round` the pass, the samples per pixel reached and the image
+/// rendered so far after every round of samples but the last one.
+pub fn render_image_rounds(
+    scene: &Scene,
+    options: &RenderOptions,
+    camera: &Camera,
+    checkpoint: Option<&str>,
+    on_tile: &(dyn Fn(&Tile, &[Rgb]) + Sync),
+    mut on_round: Option<&mut dyn FnMut(u32, u32, Rendered) -> ResultRayTracer>,
+) -> Result<Rendered, RayTracerError> {
+    let passes = pass_count(options);
+    let pixel_count = options.width * options.height;
+    let fingerprint = checkpoint_fingerprint(options, camera);
+    let mut framebuffer: Vec<Rgb> = vec![Rgb::new(0., 0., 0.); pixel_count];
+    let mut sample_counts: Vec<u32> = vec![0; pixel_count];
+    let mut aovs = Vec::new();
+    let mut first_pass = 0;
+    let mut resumed = None;
+    if let (Some(path), true) = (checkpoint, options.resume) {
+        if Path::new(path).exists() {
+            let saved = Checkpoint::read(path, AOV_COUNT).map_err(RayTracerError::Image)?;
+            if saved.fingerprint != fingerprint {
+                return Err(RayTracerError::Args(format!(
+                    "{} was written by a render with other settings",
+                    path
+                )));
+            }
+            println!("Resuming pass {} from {}", saved.pass, path);
+            first_pass = saved.pass;
+            framebuffer = saved.framebuffer;
+            sample_counts = saved.sample_counts;
+            resumed = Some(PassState {
+                pixels: saved.pixels,
+                aovs: saved.aovs,
+                splats: SplatBuffer::from_raw(options.width, options.height, &saved.splats),
+            });
+        } else {
+            println!("No checkpoint at {}, starting over", path);
+        }
+    }
+
+    let mut last_checkpoint = Instant::now();
+    let due = |last_checkpoint: Instant| {
+        last_checkpoint.elapsed().as_secs_f32() >= options.checkpoint_interval
+    };
+    let save = |path: &str, pass: u32, framebuffer: &[Rgb], counts: &[u32], state: &PassState| {
+        stats::time_phase("checkpoint", || {
+            Checkpoint {
+                fingerprint: fingerprint.clone(),
+                width: options.width,
+                height: options.height,
+                pass,
+                framebuffer: framebuffer.to_vec(),
+                sample_counts: counts.to_vec(),
+                pixels: state.pixels.clone(),
+                aovs: state.aovs.clone(),
+                splats: state.splats.raw(),
+            }
+            .write(path)
+        })
+    };
+
+    for pass in first_pass..passes {
+        let photons = pass_photons(scene, options, camera, pass);
+        let mut state = resumed
+            .take()
+            .unwrap_or_else(|| PassState::new(options, pass));
+        let mut sample_limit = 0;
+        while sample_limit < options.max_samples {
+            sample_limit = next_round_limit(options, sample_limit);
+            stats::time_phase("render", || {
+                render_round(
+                    scene,
+                    options,
+                    camera,
+                    photons.as_ref(),
+                    &mut state,
+                    sample_limit,
+                    on_tile,
+                )
+            });
+            if options.cancelled() {
+                return Err(RayTracerError::Cancelled);
+            }
+            let last_round = sample_limit == options.max_samples && pass + 1 == passes;
+            if let (Some(on_round), false) = (on_round.as_mut(), last_round) {
+                let (pixels, counts, round_aovs) = state.resolve();
+                let mut image = framebuffer.clone();
+                let mut image_counts = sample_counts.clone();
+                accumulate_pass(&mut image, &mut image_counts, pass, pixels, counts);
+                if let Some(ref settings) = options.denoise {
+                    image = denoise_image(options, settings, &image, &round_aovs);
+                }
+                on_round(pass, sample_limit, (image, image_counts, round_aovs))?;
+            }
+            match checkpoint {
+                Some(path) if sample_limit < options.max_samples && due(last_checkpoint) => {
+                    save(path, pass, &framebuffer, &sample_counts, &state)?;
+                    last_checkpoint = Instant::now();
+                }
+                _ => (),
+            }
+        }
+        let (pixels, counts, pass_aovs) = state.resolve();
+        // Progressive passes are averaged as they come.
+        accumulate_pass(&mut framebuffer, &mut sample_counts, pass, pixels, counts);
+        aovs = pass_aovs;
+        match checkpoint {
+            Some(path) if pass + 1 < passes && due(last_checkpoint) => {
+                let next = PassState::new(options, pass + 1);
+                save(path, pass + 1, &framebuffer, &sample_counts, &next)?;
+                last_checkpoint = Instant::now();
+            }
+            _ => (),
+        }
+    }
+    if let Some(ref settings) = options.denoise {
+        framebuffer = denoise_image(options, settings, &framebuffer, &aovs);
+    }
+    Ok((framebuffer, sample_counts, aovs))
+}
+
+/// Colors, sample counts, AOVs and splatted light of a pass merged from the tiles of the
+/// workers.
+struct MergedPass {
+    colors: Vec<Rgb>,
+    sample_counts: Vec<u32>,
+    aovs: Vec<Rgb>,
+    splats: Vec<[i64; 3]>,
+    tiles_left: usize,
+}
+
+/// Returns the image of `options` rendered by the workers connecting to `address`, which get
+/// `args` to load the scene with, like `render_image` does locally.
+fn render_distributed(
+    options: &RenderOptions,
+    address: &str,
+    args: &[String],
+    on_tile: &(dyn Fn(&Tile, &[Rgb]) + Sync),
+) -> Result<Rendered, RayTracerError> {
+    let (width, height) = (options.width, options.height);
+    let pixel_count = width * height;
+    let passes = pass_count(options);
+    let record_aovs = !options.aovs.is_empty() || options.denoise.is_some();
+    let tiles = tiles(width, height, options.tile_size, options.tile_order);
+    let mut framebuffer: Vec<Rgb> = vec![Rgb::new(0., 0., 0.); pixel_count];
+    let mut sample_counts: Vec<u32> = vec![0; pixel_count];
+    let mut aovs = Vec::new();
+    let mut merged: Vec<Option<MergedPass>> = (0..passes).map(|_| None).collect();
+    let mut next_pass = 0;
+    let mut merge = |result: TileResult| {
+        let black = Rgb::new(0., 0., 0.);
+        let pass = merged[result.pass as usize].get_or_insert_with(|| MergedPass {
+            colors: vec![black; pixel_count],
+            sample_counts: vec![0; pixel_count],
+            aovs: vec![
+                black;
+                if record_aovs {
+                    pixel_count * AOV_COUNT
+                } else {
+                    0
+                }
+            ],
+            splats: vec![[0; 3]; pixel_count],
+            tiles_left: tiles.len(),
+        });
+        for (k, index) in result.tile.pixels(width).enumerate() {
+            pass.colors[index] = result.colors[k];
+            pass.sample_counts[index] = result.sample_counts[k];
+            if record_aovs {
+                pass.aovs[index * AOV_COUNT..(index + 1) * AOV_COUNT]
+                    .copy_from_slice(&result.aovs[k * AOV_COUNT..(k + 1) * AOV_COUNT]);
+            }
+        }
+        for (index, splat) in &result.splats {
+            for (sum, c) in pass.splats[*index as usize].iter_mut().zip(splat) {
+                *sum += c;
+            }
+        }
+        pass.tiles_left -= 1;
+        on_tile(&result.tile, &result.colors);
+
+        // Passes are averaged in order, as when rendering locally.
+        while merged
+            .get(next_pass as usize)
+            .is_some_and(|pass| pass.as_ref().is_some_and(|pass| pass.tiles_left == 0))
+        {
+            let mut pass = merged[next_pass as usize].take().unwrap();
+            let light_paths = pass.sample_counts.iter().map(|&count| count as u64).sum();
+            SplatBuffer::from_raw(width, height, &pass.splats)
+                .resolve(&mut pass.colors, light_paths);
+            accumulate_pass(
+                &mut framebuffer,
+                &mut sample_counts,
+                next_pass,
+                pass.colors,
+                pass.sample_counts,
+            );
+            aovs = pass.aovs;
+            next_pass += 1;
+        }
+    };
+    let timeout = Duration::from_secs_f32(options.worker_timeout);
+    stats::time_phase("render", || {
+        let aovs_per_pixel = if record_aovs { AOV_COUNT } else { 0 };
+        distributed::coordinate(
+            address,
+            args,
+            &tiles,
+            passes,
+            pixel_count,
+            aovs_per_pixel,
+            timeout,
+            &mut merge,
+        )
+    })?;
+    if let Some(ref settings) = options.denoise {
+        framebuffer = denoise_image(options, settings, &framebuffer, &aovs);
+    }
+    Ok((framebuffer, sample_counts, aovs))
+}
+
+/// Combines the images of both eyes, returns the result and its size.
+fn compose_stereo(
+    layout: StereoLayout,
+    left: &[Rgb],
+    right: &[Rgb],
+    width: usize,
+    height: usize,
+) -> (Vec<Rgb>, usize, usize) {
+    match layout {
+        StereoLayout::SideBySide => {
+            let pixels = left
+                .chunks(width)
+                .zip(right.chunks(width))
+                .flat_map(|(l, r)| l.iter().chain(r.iter()).copied())
+                .collect();
+            (pixels, 2 * width, height)
+        }
+        StereoLayout::TopBottom => {
+            let pixels = left.iter().chain(right.iter()).copied().collect();
+            (pixels, width, 2 * height)
+        }
+        StereoLayout::Anaglyph => {
+            let pixels = left
+                .iter()
+                .zip(right.iter())
+                .map(|(l, r)| Rgb::new(l.r, r.g, r.b))
+                .collect();
+            (pixels, width, height)
+        }
+    }
+}
+
+/// Sample counts relative to the maximum in false color.
+fn heatmap_pixels(options: &RenderOptions, sample_counts: &[u32]) -> Vec<Rgb> {
+    sample_counts
+        .iter()
+        .map(|&count| false_color(count as f32 / options.max_samples as f32))
+        .collect()
+}
+
+/// Writes the image of a single camera and the requested extra outputs.
+fn write_outputs(options: &RenderOptions, rendered: Rendered) -> ResultRayTracer {
+    let (width, height) = (options.width, options.height);
+    let (framebuffer, sample_counts, aovs) = rendered;
+    let aov_output = options.aov_output.as_ref().unwrap_or(&options.output);
+    let layered = !options.aovs.is_empty() && has_extension(aov_output, "exr");
+    // The image is a layer of the EXR file holding the AOVs.
+    if !layered || aov_output != &options.output {
+        write_image(&options.output, width, height, &framebuffer)?;
+    }
+    if !options.aovs.is_empty() {
+        write_aovs(aov_output, options, &framebuffer, &aovs)?;
+    }
+    if let Some(ref heatmap) = options.heatmap {
+        write_image(
+            heatmap,
+            width,
+            height,
+            &heatmap_pixels(options, &sample_counts),
+        )?;
+    }
+    Ok(())
+}
+
+/// Renders and writes the image and the requested extra outputs, `on_tile` receives every
+/// finished tile.
+pub fn render(
+    scene: &Scene,
+    options: &RenderOptions,
+    on_tile: &(dyn Fn(&Tile, &[Rgb]) + Sync),
+) -> ResultRayTracer {
+    let (width, height) = (options.width, options.height);
+    match options.stereo {
+        None => {
+            let checkpoint = options.checkpoint.as_deref();
+            let rendered = render_image(scene, options, &options.camera, checkpoint, on_tile)?;
+            stats::time_phase("output", || write_outputs(options, rendered))
+        }
+        Some(stereo) => {
+            let checkpoint = |eye: &str| options.checkpoint.as_ref().map(|p| suffixed_path(p, eye));
+            let (left, left_counts, _) = render_image(
+                scene,
+                options,
+                &stereo.eye(&options.camera, true),
+                checkpoint("left").as_deref(),
+                on_tile,
+            )?;
+            let (right, right_counts, _) = render_image(
+                scene,
+                options,
+                &stereo.eye(&options.camera, false),
+                checkpoint("right").as_deref(),
+                on_tile,
+            )?;
+            stats::time_phase("output", || {
+                let (pixels, w, h) = compose_stereo(stereo.layout, &left, &right, width, height);
+                write_image(&options.output, w, h, &pixels)?;
+                if let Some(ref heatmap) = options.heatmap {
+                    let (left, right) = (
+                        heatmap_pixels(options, &left_counts),
+                        heatmap_pixels(options, &right_counts),
+                    );
+                    let (pixels, w, h) =
+                        compose_stereo(stereo.layout, &left, &right, width, height);
+                    write_image(heatmap, w, h, &pixels)?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+/// The `denoise` subcommand: filters a PFM or EXR render with its albedo and normal AOVs,
+/// the layers of an EXR input or the files written next to a PFM one unless given. EXR files
+/// of other tools must be uncompressed or ZIP compressed, see `float_image`.
+fn denoise_command(args: &[String]) -> ResultRayTracer {
+    let mut input = None;
+    let mut output = None;
+    let mut guides: [Option<String>; 2] = [None, None];
+    let mut settings = DenoiseSettings::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| RayTracerError::Args(format!("missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--albedo" => guides[0] = Some(value()?),
+            "--normal" => guides[1] = Some(value()?),
+            "--iterations" => settings.iterations = parse_value(&value()?)?,
+            "-o" | "--output" => output = Some(value()?),
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(arg.clone()),
+            _ => return Err(RayTracerError::Args(format!("unknown argument {}", arg))),
+        }
+    }
+    let input = input.ok_or_else(|| {
+        RayTracerError::Args(String::from(
+            "usage: denoise <image.exr|image.pfm> [-o output] [--albedo file] [--normal file], \
+             EXR files are uncompressed or ZIP compressed",
+        ))
+    })?;
+    let output = output.unwrap_or_else(|| suffixed_path(&input, "denoised"));
+
+    let image = read_float_image(Path::new(&input)).map_err(RayTracerError::Image)?;
+    let missing = |what: &str| RayTracerError::Image(format!("{}: no {}", input, what));
+    let color = image.layer("").ok_or_else(|| missing("RGB channels"))?;
+    let mut layers = Vec::new();
+    for (guide, aov) in guides.iter().zip(&[Aov::Albedo, Aov::Normal]) {
+        let pixels = match guide {
+            Some(path) => read_guide(path, image.width, image.height)?,
+            None if has_extension(&input, "exr") => {
+                image.layer(aov.name()).ok_or_else(|| missing(aov.name()))?
+            }
+            None => read_guide(
+                &suffixed_path(&input, aov.name()),
+                image.width,
+                image.height,
+            )?,
+        };
+        layers.push(pixels);
+    }
+    let pixels = denoise(
+        image.width,
+        image.height,
+        &color,
+        &layers[0],
+        &layers[1],
+        &settings,
+    );
+    write_image(&output, image.width, image.height, &pixels)
+}
+
+/// Colors of a guide image of the denoiser, which must have the size of the image.
+fn read_guide(path: &str, width: usize, height: usize) -> Result<Vec<Rgb>, RayTracerError> {
+    let image = read_float_image(Path::new(path)).map_err(RayTracerError::Image)?;
+    if (image.width, image.height) != (width, height) {
+        return Err(RayTracerError::Image(format!(
+            "{}: {}x{} instead of {}x{}",
+            path, image.width, image.height, width, height
+        )));
+    }
+    image
+        .layer("")
+        .ok_or_else(|| RayTracerError::Image(format!("{}: no RGB channels", path)))
+}
+
+/// Width, height and colors of a PFM or EXR image, or of an 8-bit image the image crate reads.
+fn read_any_image(path: &str) -> Result<(usize, usize, Vec<Rgb>), RayTracerError> {
+    if has_extension(path, "pfm") || has_extension(path, "exr") {
+        let image = read_float_image(Path::new(path)).map_err(RayTracerError::Image)?;
+        let pixels = image
+            .layer("")
+            .ok_or_else(|| RayTracerError::Image(format!("{}: no RGB channels", path)))?;
+        return Ok((image.width, image.height, pixels));
+    }
+    let image = image::open(path)?.to_rgb();
+    let pixels = image.pixels().map(|p| Rgb::from_rgb8(p.data)).collect();
+    Ok((image.width() as usize, image.height() as usize, pixels))
+}
+
+/// The `compare` subcommand: prints the differences between a test image and a reference
+/// one, and writes the per pixel errors of one of the metrics in false color. EXR images must
+/// be uncompressed or ZIP compressed, see `float_image`.
+fn compare_command(args: &[String]) -> ResultRayTracer {
+    let mut paths = Vec::new();
+    let mut output = None;
+    let mut metric = Metric::Flip;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| RayTracerError::Args(format!("missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--metric" => {
+                let name = value()?;
+                metric = Metric::from_name(&name)
+                    .ok_or_else(|| RayTracerError::Args(format!("unknown metric {}", name)))?;
+            }
+            "-o" | "--output" => output = Some(value()?),
+            _ if paths.len() < 2 && !arg.starts_with('-') => paths.push(arg.clone()),
+            _ => return Err(RayTracerError::Args(format!("unknown argument {}", arg))),
+        }
+    }
+    let usage = "usage: compare <reference> <test> [-o heatmap] [--metric rmse|psnr|ssim|flip], \
+                 images are PPM, PNG, JPEG, PFM or uncompressed or ZIP compressed EXR";
+    let (reference_path, test_path) = match paths.as_slice() {
+        [reference, test] => (reference, test),
+        _ => return Err(RayTracerError::Args(String::from(usage))),
+    };
+    let (width, height, reference) = read_any_image(reference_path)?;
+    let (test_width, test_height, test) = read_any_image(test_path)?;
+    if (test_width, test_height) != (width, height) {
+        return Err(RayTracerError::Image(format!(
+            "{}: {}x{} instead of {}x{}",
+            test_path, test_width, test_height, width, height
+        )));
+    }
+
+    println!("MSE:    {:.6}", metrics::mse(&reference, &test));
+    println!("relMSE: {:.6}", metrics::relative_mse(&reference, &test));
+    println!("PSNR:   {:.2} dB", metrics::psnr(&reference, &test));
+    let ssim = metrics::compare(Metric::Ssim, &reference, &test, width, height);
+    println!("SSIM:   {:.4}", ssim.value);
+    let flip = metrics::compare(Metric::Flip, &reference, &test, width, height);
+    println!("FLIP:   {:.4}", flip.value);
+    if let Some(path) = output {
+        let comparison = match metric {
+            Metric::Ssim => ssim,
+            Metric::Flip => flip,
+            _ => metrics::compare(metric, &reference, &test, width, height),
+        };
+        write_image(&path, width, height, &comparison.heatmap())?;
+    }
+    Ok(())
+}
+
+fn default_scene(shutter: (f32, f32)) -> Result<Scene, RayTracerError> {
+    let envmap = Path::new("./envmap.jpg");
+    let (background, sun) = if envmap.exists() {
+        (EnvMap::load(envmap)?, None)
+    } else {
+        println!("No {}, using the default sky", envmap.display());
+        sky::bake(&SkySettings::default())
+    };
+
+    let ivory = Material {
+        refractive_index: 1.,
+        albedo: Rgba::new(0.6, 0.3, 0.1, 0.),
+        diffuse_color: Rgb::new(0.4, 0.4, 0.3),
+        specular_exponent: 50.,
+        medium: None,
+        dispersion: None,
+        emission: None,
+    };
+    let glass = Material {
+        refractive_index: 1.5,
+        albedo: Rgba::new(0., 0.5, 0.1, 0.8),
+        diffuse_color: Rgb::new(0.6, 0.7, 0.8),
+        specular_exponent: 125.,
+        medium: None,
+        dispersion: None,
+        emission: None,
+    };
+    let red_rubber = Material {
+        refractive_index: 1.,
+        albedo: Rgba::new(0.9, 0.1, 0., 0.),
+        diffuse_color: Rgb::new(0.3, 0.1, 0.1),
+        specular_exponent: 10.,
+        medium: None,
+        dispersion: None,
+        emission: None,
+    };
+    let mirror = Material {
+        refractive_index: 1.,
+        albedo: Rgba::new(0., 10., 0.8, 0.),
+        diffuse_color: Rgb::new(1., 1., 1.),
+        specular_exponent: 1425.,
+        medium: None,
+        dispersion: None,
+        emission: None,
+    };
+
+    let mut spheres = vec![
+        Sphere::new(Vec3f32::new(-3.0, 0.0, -16.0), 2.0, ivory),
+        Sphere::new(Vec3f32::new(-1.0, -1.5, -12.0), 2.0, glass),
+        Sphere::new(Vec3f32::new(1.5, -0.5, -18.0), 3.0, red_rubber),
+        Sphere::new(Vec3f32::new(7.0, 5.0, -18.0), 4.0, mirror),
+    ];
+    // Every sphere has its own material.
+    for (i, sphere) in spheres.iter_mut().enumerate() {
+        sphere.material_id = i;
+    }
+
+    let lights = vec![
+        Light {
+            position: Vec3f32::new(-20., 20., 20.),
+            intensity: 1.5,
+        },
+        Light {
+            position: Vec3f32::new(30., 50., -25.),
+            intensity: 1.8,
+        },
+        Light {
+            position: Vec3f32::new(30., 20., 30.),
+            intensity: 1.7,
+        },
+    ];
+    let mut scene = Scene::new(spheres, lights, background, shutter);
+    scene.sun = sun;
+    Ok(scene)
+}
+
+/// Loads the scene of the options, with the camera, sky and lights they override.
+pub fn load(options: &mut RenderOptions) -> Result<(Scene, Animation), RayTracerError> {
+    let (mut scene, animation) = stats::time_phase("scene", || match options.scene {
+        Some(ref path) => {
+            let file = load_scene(Path::new(path), &mut options.camera)?;
+            Ok::<_, RayTracerError>((file.scene, file.animation))
+        }
+        None => {
+            let shutter = (options.camera.shutter_open, options.camera.shutter_close);
+            Ok((default_scene(shutter)?, Animation::default()))
+        }
+    })?;
+    apply_options(options, &mut scene)?;
+    Ok((scene, animation))
+}
+
+/// Overrides the camera, sky and lights of a loaded scene with the ones of the options.
+pub fn apply_options(options: &mut RenderOptions, scene: &mut Scene) -> ResultRayTracer {
+    if options.spectral && !scene.media.is_empty() {
+        return Err(RayTracerError::Scene(String::from(
+            "spectral mode does not render participating media",
+        )));
+    }
+    if let Some(fov) = options.fov {
+        options.camera.fov = fov;
+    }
+    if let Some(f_stop) = options.f_stop {
+        options.camera.set_f_stop(f_stop);
+    }
+    if let Some(ref settings) = options.sky {
+        let (background, sun) = stats::time_phase("sky", || sky::bake(settings));
+        scene.background = background;
+        scene.sun = sun;
+    }
+    if options.ao_shading {
+        scene.ambient_occlusion = Some(options.ao);
+    }
+    if let Some(samples) = options.env_samples {
+        let light = stats::time_phase("scene", || EnvLight::new(&scene.background, samples));
+        scene.env_light = Some(light);
+    }
+    Ok(())
+}
+
+/// Poses `scene` for `frame` and returns the options rendering it.
+pub fn setup_frame(
+    options: &RenderOptions,
+    frame: i32,
+    scene: &mut Scene,
+    animation: &Animation,
+) -> RenderOptions {
+    let mut frame_options = options.clone();
+    if options.frames.is_some() {
+        frame_options.output = frame_path(&options.output, frame);
+        frame_options.heatmap = options.heatmap.as_ref().map(|p| frame_path(p, frame));
+        frame_options.aov_output = options.aov_output.as_ref().map(|p| frame_path(p, frame));
+        frame_options.checkpoint = options.checkpoint.as_ref().map(|p| frame_path(p, frame));
+    }
+    if !animation.tracks.is_empty() {
+        animation.apply(frame as f32, scene, &mut frame_options.camera);
+        let camera = &frame_options.camera;
+        stats::time_phase("scene", || {
+            scene.build_bvh((camera.shutter_open, camera.shutter_close))
+        });
+    }
+
+    if let Some((x, y)) = options.autofocus {
+        let (camera, width, height) = (&frame_options.camera, options.width, options.height);
+        match autofocus(camera, width, height, x, y, scene) {
+            Some(distance) => frame_options.camera.focus_distance = distance,
+            None => println!("Autofocus: nothing under pixel {},{}", x, y),
+        }
+    }
+    frame_options
+}
+
+/// Renders the first frame of the command line `args` to memory, without writing any file.
+/// Returns the width, the height and the pixels of the image.
+pub fn render_args(args: &[String]) -> Result<(usize, usize, Vec<Rgb>), RayTracerError> {
+    let mut options = parse_args(args)?;
+    let (mut scene, animation) = load(&mut options)?;
+    let options = setup_frame(&options, 0, &mut scene, &animation);
+    let (pixels, _, _) = render_image(&scene, &options, &options.camera, None, &|_, _| ())?;
+    Ok((options.width, options.height, pixels))
+}
+
+/// The `worker` subcommand: renders tiles for the coordinator listening at the given address
+/// until it has no more.
+fn worker_command(args: &[String]) -> ResultRayTracer {
+    let address = match args {
+        [address] => address,
+        _ => {
+            return Err(RayTracerError::Args(String::from(
+                "usage: ray_tracer worker <host:port>",
+            )))
+        }
+    };
+    let (worker, job) = Worker::connect(address)?;
+    let mut options = parse_args(&job)?;
+    let (mut scene, animation) = load(&mut options)?;
+    let options = setup_frame(&options, 0, &mut scene, &animation);
+    println!("Rendering for {}", address);
+
+    // Photons of the last pass, the tiles come pass after pass.
+    let photons: Mutex<Option<(u32, Arc<PhotonMaps>)>> = Mutex::new(None);
+    let splat_size = match options.integrator {
+        Integrator::Bidirectional => (options.width, options.height),
+        _ => (0, 0),
+    };
+    worker.run(
+        rayon::current_num_threads(),
+        || SplatBuffer::new(splat_size.0, splat_size.1),
+        |splats, pass, tile| {
+            let maps = {
+                let mut cached = photons.lock().unwrap();
+                match *cached {
+                    Some((cached_pass, ref maps)) if cached_pass == pass => Some(Arc::clone(maps)),
+                    _ => {
+                        let maps =
+                            pass_photons(&scene, &options, &options.camera, pass).map(Arc::new);
+                        *cached = maps.as_ref().map(|maps| (pass, Arc::clone(maps)));
+                        maps
+                    }
+                }
+            };
+            render_tile(&scene, &options, maps.as_deref(), splats, pass, tile)
+        },
+    )?;
+    Ok(())
+}
+
+/// Runs the command line `args`, without the name of the program.
+pub fn run(args: &[String]) -> ResultRayTracer {
+    match args.first().map(String::as_str) {
+        Some("denoise") => return denoise_command(&args[1..]),
+        Some("compare") => return compare_command(&args[1..]),
+        Some("worker") => return worker_command(&args[1..]),
+        Some("serve") => return server::serve_command(&args[1..]),
+        _ => (),
+    }
+
+    let mut options = parse_args(args)?;
+    if options.watch {
+        return watch::watch(&options);
+    }
+    let before = stats::snapshot();
+    let (mut scene, animation) = load(&mut options)?;
+
+    let start = Instant::now();
+
+    let (first_frame, last_frame) = options.frames.unwrap_or((0, 0));
+    for frame in first_frame..=last_frame {
+        let frame_options = setup_frame(&options, frame, &mut scene, &animation);
+
+        let label = match options.frames {
+            Some(_) => format!("Frame {}", frame),
+            None => String::from("Rendering"),
+        };
+        let eyes = if options.stereo.is_some() { 2 } else { 1 };
+        let pixels = options.width
+            * options.height
+            * (pass_count(&options) * round_count(&options)) as usize
+            * eyes;
+        let progress = Progress::new(
+            &label,
+            pixels,
+            options.progress && io::stderr().is_terminal(),
+        );
+        let preview = options
+            .preview
+            .then(|| Preview::new(options.width, options.height));
+        let on_tile = |tile: &Tile, colors: &[Rgb]| {
+            progress.advance(tile.width * tile.height);
+            if let Some(ref preview) = preview {
+                preview.update(tile, colors);
+            }
+        };
+        match options.listen {
+            Some(ref address) => {
+                let rendered = render_distributed(&frame_options, address, args, &on_tile)?;
+                stats::time_phase("output", || write_outputs(&frame_options, rendered))?;
+            }
+            None => render(&scene, &frame_options, &on_tile)?,
+        }
+        if let Some(preview) = preview {
+            preview.finish();
+        }
+        progress.finish();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "Elapsed: {} ms",
+        (elapsed.as_secs() * 1_000) + elapsed.subsec_millis() as u64
+    );
+    let report = Report::since(&before);
+    print!("{}", report);
+    if let Some(ref path) = options.stats_json {
+        fs::write(path, report.to_json())?;
+    }
+    Ok(())
+}
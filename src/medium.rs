@@ -0,0 +1,328 @@
+//! Participating media for `cast_ray`. Homogeneous media are ray marched for the single
+//! scattering of the point lights, heterogeneous media take their density from a grid and are
+//! sampled with delta tracking.
+//!
+//! Density grid files start with the magic `GRID`, the resolution as three little endian `u32`
+//! and then the densities as little endian `f32`, x varying fastest, then y, then z.
+
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::bvh::Aabb;
+use crate::color::Rgb;
+use crate::sampler::Rng;
+use crate::scene::{offset_origin, scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Number of ray marching steps along a ray segment in a homogeneous medium.
+const MARCH_STEPS: usize = 32;
+
+/// Transmittance below which a medium is considered opaque, bounds the marching distance of
+/// rays escaping to the env map.
+const MIN_TRANSMITTANCE: f32 = 1e-3;
+
+#[derive(Debug)]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    densities: Vec<f32>,
+    /// World space box the grid is stretched over.
+    bounds: Aabb,
+    max_density: f32,
+}
+
+impl DensityGrid {
+    pub fn load(path: &Path, bounds: Aabb) -> Result<DensityGrid, String> {
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if bytes.len() < 16 || &bytes[..4] != b"GRID" {
+            return Err(format!("{}: not a density grid", path.display()));
+        }
+        let word = |i: usize| {
+            let mut b = [0; 4];
+            b.copy_from_slice(&bytes[4 * i..4 * i + 4]);
+            b
+        };
+        let resolution = [1, 2, 3].map(|i| u32::from_le_bytes(word(i)) as usize);
+        let count = resolution[0] * resolution[1] * resolution[2];
+        if count == 0 || bytes.len() != 16 + 4 * count {
+            return Err(format!(
+                "{}: expected {}x{}x{} densities",
+                path.display(),
+                resolution[0],
+                resolution[1],
+                resolution[2]
+            ));
+        }
+        let densities: Vec<f32> = (0..count)
+            .map(|i| f32::from_le_bytes(word(4 + i)).max(0.))
+            .collect();
+        let max_density = densities.iter().cloned().fold(0., f32::max);
+        Ok(DensityGrid {
+            resolution,
+            densities,
+            bounds,
+            max_density,
+        })
+    }
+
+    fn voxel(&self, x: isize, y: isize, z: isize) -> f32 {
+        let [nx, ny, nz] = self.resolution;
+        let x = x.clamp(0, nx as isize - 1) as usize;
+        let y = y.clamp(0, ny as isize - 1) as usize;
+        let z = z.clamp(0, nz as isize - 1) as usize;
+        self.densities[(z * ny + y) * nx + x]
+    }
+
+    /// Trilinear interpolation of the voxels, zero outside of the bounds.
+    fn density(&self, p: &Vec3f32) -> f32 {
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        if p.x < min.x || p.y < min.y || p.z < min.z || p.x > max.x || p.y > max.y || p.z > max.z {
+            return 0.;
+        }
+        let size = max - min;
+        let local = [
+            (p.x - min.x) / size.x * self.resolution[0] as f32 - 0.5,
+            (p.y - min.y) / size.y * self.resolution[1] as f32 - 0.5,
+            (p.z - min.z) / size.z * self.resolution[2] as f32 - 0.5,
+        ];
+        let base = local.map(|c| c.floor());
+        let [fx, fy, fz] = [0, 1, 2].map(|i| local[i] - base[i]);
+        let [x, y, z] = base.map(|c| c as isize);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z: isize| {
+            lerp(
+                lerp(self.voxel(x, y, z), self.voxel(x + 1, y, z), fx),
+                lerp(self.voxel(x, y + 1, z), self.voxel(x + 1, y + 1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z), plane(z + 1), fz)
+    }
+}
+
+#[derive(Debug)]
+pub struct Medium {
+    /// Absorption and scattering coefficients per unit of distance, scaled by the grid density.
+    pub absorption: Rgb,
+    pub scattering: Rgb,
+    /// Henyey-Greenstein asymmetry, positive values scatter forward.
+    pub g: f32,
+    pub grid: Option<DensityGrid>,
+}
+
+// https://www.pbr-book.org/3ed-2018/Volume_Scattering/Phase_Functions
+/// Henyey-Greenstein phase function, `cos_theta` is the cosine between the directions toward
+/// the viewer and toward the light.
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1. + g * g + 2. * g * cos_theta;
+    (1. - g * g) / (4. * PI * denom * denom.max(1e-6).sqrt())
+}
+
+fn max_component(c: &Rgb) -> f32 {
+    c.r.max(c.g).max(c.b)
+}
+
+impl Medium {
+    fn extinction(&self) -> Rgb {
+        self.absorption + self.scattering
+    }
+
+    /// Transmittance of a homogeneous medium over `distance`, which may be infinite.
+    fn homogeneous_transmittance(&self, distance: f32) -> Rgb {
+        let sigma_t = self.extinction();
+        let channel = |s: f32| if s > 0. { (-s * distance).exp() } else { 1. };
+        Rgb::new(channel(sigma_t.r), channel(sigma_t.g), channel(sigma_t.b))
+    }
+
+    /// Fraction of the light going through the medium over `distance` along the ray, estimated
+    /// with ratio tracking for density grids.
+    pub fn transmittance(
+        &self,
+        orig: &Vec3f32,
+        dir: &Vec3f32,
+        distance: f32,
+        rng: &mut Rng,
+    ) -> Rgb {
+        let grid = match self.grid {
+            Some(ref grid) => grid,
+            None => return self.homogeneous_transmittance(distance),
+        };
+        let mut transmittance = Rgb::new(1., 1., 1.);
+        let sigma_t = self.extinction();
+        let majorant = max_component(&sigma_t) * grid.max_density;
+        let (mut t, t_end) = match grid.bounds.clip(orig, dir, distance) {
+            Some(range) if majorant > 0. => range,
+            _ => return transmittance,
+        };
+        loop {
+            t -= (1. - rng.next_f32()).ln() / majorant;
+            if t >= t_end {
+                return transmittance;
+            }
+            let density = grid.density(&(orig + dir * t));
+            let ratio = Rgb::new(1., 1., 1.) - sigma_t * (density / majorant);
+            transmittance = transmittance * ratio;
+            if max_component(&transmittance) <= 0. {
+                return Rgb::new(0., 0., 0.);
+            }
+        }
+    }
+}
+
+/// Transmittance toward a light `distance` away, shadow rays go through the boundaries of
+/// media, any other surface blocks the light.
+pub fn shadow_transmittance(
+    scene: &Scene,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    distance: f32,
+    medium: Option<usize>,
+    time: f32,
+    rng: &mut Rng,
+) -> Rgb {
+    let (mut orig, mut remaining, mut medium) = (*orig, distance, medium);
+    let mut transmittance = Rgb::new(1., 1., 1.);
+    // Bounds the number of boundaries crossed in case of numerical trouble.
+    for _ in 0..16 {
+        let mut hit = Vec3f32::new(0., 0., 0.);
+        let mut n = Vec3f32::new(0., 0., 0.);
+        let mut material = Material::default();
+        stats::count(Counter::ShadowRays, 1);
+        let hit_distance =
+            if scene_intersect(&orig, dir, time, scene, &mut hit, &mut n, &mut material) {
+                (hit - orig).norm()
+            } else {
+                f32::INFINITY
+            };
+        if let Some(index) = medium {
+            let segment = hit_distance.min(remaining);
+            transmittance =
+                transmittance * scene.media[index].transmittance(&orig, dir, segment, rng);
+        }
+        if hit_distance >= remaining {
+            return transmittance;
+        }
+        if material.medium.is_none() {
+            break;
+        }
+        medium = if dir.dot_product(&n) < 0. {
+            material.medium
+        } else {
+            scene.fog
+        };
+        remaining -= hit_distance;
+        orig = offset_origin(&hit, &n, dir);
+    }
+    Rgb::new(0., 0., 0.)
+}
+
+/// Light of the point lights scattered at `p` toward `-dir`, with Whitted's intensities.
+fn in_scattered(
+    scene: &Scene,
+    medium: usize,
+    p: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    rng: &mut Rng,
+) -> Rgb {
+    let mut sum = Rgb::new(0., 0., 0.);
+    for light in &scene.lights {
+        let mut light_dir = light.position - p;
+        let light_distance = light_dir.norm();
+        light_dir.normalize();
+        let transmittance = shadow_transmittance(
+            scene,
+            p,
+            &light_dir,
+            light_distance,
+            Some(medium),
+            time,
+            rng,
+        );
+        let phase = henyey_greenstein(-dir.dot_product(&light_dir), scene.media[medium].g);
+        sum = sum + transmittance * (light.intensity * phase);
+    }
+    sum
+}
+
+/// Transmittance of the ray segment (`orig`, `dir`) of length `distance` in `medium` and the
+/// light scattered toward its origin.
+pub fn integrate(
+    scene: &Scene,
+    medium: usize,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    distance: f32,
+    time: f32,
+    rng: &mut Rng,
+) -> (Rgb, Rgb) {
+    let m = &scene.media[medium];
+    let black = Rgb::new(0., 0., 0.);
+    let sigma_t = m.extinction();
+
+    let grid = match m.grid {
+        Some(ref grid) => grid,
+        None => {
+            let transmittance = m.homogeneous_transmittance(distance);
+            let min_sigma_t = sigma_t.r.min(sigma_t.g).min(sigma_t.b);
+            let end = if distance.is_finite() {
+                distance
+            } else if min_sigma_t > 0. {
+                -MIN_TRANSMITTANCE.ln() / min_sigma_t
+            } else {
+                return (transmittance, black);
+            };
+            if max_component(&m.scattering) <= 0. {
+                return (transmittance, black);
+            }
+            // Stratified steps with one random offset, the single scattering integral.
+            let step = end / MARCH_STEPS as f32;
+            let offset = rng.next_f32();
+            let mut scattered = black;
+            for i in 0..MARCH_STEPS {
+                let s = (i as f32 + offset) * step;
+                let li = in_scattered(scene, medium, &(orig + dir * s), dir, time, rng);
+                scattered = scattered + m.homogeneous_transmittance(s) * m.scattering * li * step;
+            }
+            return (transmittance, scattered);
+        }
+    };
+
+    // Delta tracking generalized to colored coefficients: each tentative collision picks
+    // scattering, absorption or a null collision in proportion to their largest channel and
+    // the path is reweighted per channel.
+    // https://jannovak.info/publications/SDTracking/SDTracking.pdf
+    let majorant = max_component(&sigma_t) * grid.max_density;
+    let (mut t, t_end) = match grid.bounds.clip(orig, dir, distance) {
+        Some(range) if majorant > 0. => range,
+        _ => return (Rgb::new(1., 1., 1.), black),
+    };
+    let mut weight = Rgb::new(1., 1., 1.);
+    loop {
+        t -= (1. - rng.next_f32()).ln() / majorant;
+        if t >= t_end {
+            return (weight, black);
+        }
+        let p = orig + dir * t;
+        let density = grid.density(&p);
+        let scattering = m.scattering * density;
+        let absorption = m.absorption * density;
+        let null = Rgb::new(majorant, majorant, majorant) - (scattering + absorption);
+        let (p_scatter, p_absorb, p_null) = (
+            max_component(&scattering),
+            max_component(&absorption),
+            max_component(&null).max(0.),
+        );
+        let total = p_scatter + p_absorb + p_null;
+        let u = rng.next_f32() * total;
+        if u < p_scatter {
+            weight = weight * scattering * (total / (majorant * p_scatter));
+            let li = in_scattered(scene, medium, &p, dir, time, rng);
+            return (black, weight * li);
+        } else if u < p_scatter + p_absorb {
+            return (black, black);
+        }
+        weight = weight * null * (total / (majorant * p_null));
+    }
+}
@@ -0,0 +1,406 @@
+//! Differences between a test image and a reference one. The mean squared errors and the
+//! PSNR compare the float values of the renderer, SSIM and FLIP compare what is displayed,
+//! the values written to 8-bit images, read as sRGB.
+
+use crate::color::{false_color, Rgb};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Rmse,
+    Psnr,
+    Ssim,
+    Flip,
+}
+
+impl Metric {
+    pub fn from_name(name: &str) -> Option<Metric> {
+        match name {
+            "rmse" => Some(Metric::Rmse),
+            "psnr" => Some(Metric::Psnr),
+            "ssim" => Some(Metric::Ssim),
+            "flip" => Some(Metric::Flip),
+            _ => None,
+        }
+    }
+
+    /// Whether closer images have higher values.
+    pub fn higher_is_better(self) -> bool {
+        matches!(self, Metric::Psnr | Metric::Ssim)
+    }
+
+    /// Whether `value` is as good as `tolerance`, a minimum or a maximum for the metric.
+    pub fn within(self, value: f64, tolerance: f64) -> bool {
+        if self.higher_is_better() {
+            value >= tolerance
+        } else {
+            value <= tolerance
+        }
+    }
+}
+
+/// Value of a metric over the whole image and the error of every pixel, in [0, 1].
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub value: f64,
+    pub errors: Vec<f32>,
+}
+
+impl Comparison {
+    /// Errors of the pixels in false color.
+    pub fn heatmap(&self) -> Vec<Rgb> {
+        self.errors.iter().map(|&e| false_color(e)).collect()
+    }
+}
+
+pub fn compare(
+    metric: Metric,
+    reference: &[Rgb],
+    test: &[Rgb],
+    width: usize,
+    height: usize,
+) -> Comparison {
+    match metric {
+        Metric::Rmse | Metric::Psnr => {
+            let value = match metric {
+                Metric::Rmse => mse(reference, test).sqrt(),
+                _ => psnr(reference, test),
+            };
+            let errors: Vec<f32> = reference
+                .iter()
+                .zip(test)
+                .map(|(r, t)| squared_error(*r, *t).sqrt())
+                .collect();
+            // Scaled by the largest error, whatever the range of the values.
+            let max = errors.iter().cloned().fold(0., f32::max).max(1e-9);
+            Comparison {
+                value,
+                errors: errors.iter().map(|e| e / max).collect(),
+            }
+        }
+        Metric::Ssim => {
+            let map = ssim_map(reference, test, width, height);
+            Comparison {
+                value: mean(&map),
+                errors: map.iter().map(|s| (1. - s).clamp(0., 1.)).collect(),
+            }
+        }
+        Metric::Flip => {
+            let map = flip_map(reference, test, width, height, DEFAULT_PPD);
+            Comparison {
+                value: mean(&map),
+                errors: map,
+            }
+        }
+    }
+}
+
+fn mean(values: &[f32]) -> f64 {
+    values.iter().map(|&v| v as f64).sum::<f64>() / values.len().max(1) as f64
+}
+
+/// Mean of the squared differences of the channels.
+fn squared_error(reference: Rgb, test: Rgb) -> f32 {
+    let d = test - reference;
+    (d.r * d.r + d.g * d.g + d.b * d.b) / 3.
+}
+
+pub fn mse(reference: &[Rgb], test: &[Rgb]) -> f64 {
+    let errors: Vec<f32> = reference
+        .iter()
+        .zip(test)
+        .map(|(r, t)| squared_error(*r, *t))
+        .collect();
+    mean(&errors)
+}
+
+/// Squared differences relative to the squared reference, which weighs the errors in dark
+/// areas like the ones in bright areas.
+pub fn relative_mse(reference: &[Rgb], test: &[Rgb]) -> f64 {
+    let errors: Vec<f32> = reference
+        .iter()
+        .zip(test)
+        .map(|(r, t)| {
+            let relative = |r: f32, t: f32| (t - r) * (t - r) / (r * r + 0.01);
+            (relative(r.r, t.r) + relative(r.g, t.g) + relative(r.b, t.b)) / 3.
+        })
+        .collect();
+    mean(&errors)
+}
+
+/// Peak signal to noise ratio in decibels for a peak of 1, infinite for identical images.
+pub fn psnr(reference: &[Rgb], test: &[Rgb]) -> f64 {
+    -10. * mse(reference, test).log10()
+}
+
+/// Convolution with the kernel `kx` along the rows then `ky` along the columns, both of odd
+/// length, repeating the pixels of the edges.
+fn convolve(image: &[f32], width: usize, height: usize, kx: &[f32], ky: &[f32]) -> Vec<f32> {
+    let (rx, ry) = (kx.len() / 2, ky.len() / 2);
+    let mut rows = vec![0.; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            rows[y * width + x] = kx
+                .iter()
+                .enumerate()
+                .map(|(k, w)| {
+                    let sx = (x + k).saturating_sub(rx).min(width - 1);
+                    w * image[y * width + sx]
+                })
+                .sum();
+        }
+    }
+    let mut result = vec![0.; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            result[y * width + x] = ky
+                .iter()
+                .enumerate()
+                .map(|(k, w)| {
+                    let sy = (y + k).saturating_sub(ry).min(height - 1);
+                    w * rows[sy * width + x]
+                })
+                .sum();
+        }
+    }
+    result
+}
+
+fn gaussian(sigma: f32, radius: usize) -> Vec<f32> {
+    let kernel: Vec<f32> = (0..2 * radius + 1)
+        .map(|k| {
+            let x = k as f32 - radius as f32;
+            (-x * x / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|w| w / sum).collect()
+}
+
+/// Structural similarity of the displayed luminances, with an 11 by 11 gaussian window.
+// https://ece.uwaterloo.ca/~z70wang/publications/ssim.pdf
+fn ssim_map(reference: &[Rgb], test: &[Rgb], width: usize, height: usize) -> Vec<f32> {
+    let luminance = |pixels: &[Rgb]| -> Vec<f32> {
+        pixels.iter().map(|p| p.to_display().luminance()).collect()
+    };
+    let (a, b) = (luminance(reference), luminance(test));
+    let window = gaussian(1.5, 5);
+    let blur = |image: &[f32]| convolve(image, width, height, &window, &window);
+    let product =
+        |u: &[f32], v: &[f32]| -> Vec<f32> { u.iter().zip(v).map(|(x, y)| x * y).collect() };
+    let (mean_a, mean_b) = (blur(&a), blur(&b));
+    let (aa, bb, ab) = (
+        blur(&product(&a, &a)),
+        blur(&product(&b, &b)),
+        blur(&product(&a, &b)),
+    );
+    let (c1, c2) = (0.01f32.powi(2), 0.03f32.powi(2));
+    (0..a.len())
+        .map(|i| {
+            let (ma, mb) = (mean_a[i], mean_b[i]);
+            let (va, vb, cov) = (aa[i] - ma * ma, bb[i] - mb * mb, ab[i] - ma * mb);
+            ((2. * ma * mb + c1) * (2. * cov + c2)) / ((ma * ma + mb * mb + c1) * (va + vb + c2))
+        })
+        .collect()
+}
+
+/// Pixels per degree of a 0.7 meter wide 4K monitor seen from 0.7 meters, the default
+/// observer of FLIP.
+const DEFAULT_PPD: f32 = 67.02;
+
+/// White point of the XYZ values of linear sRGB.
+const D65: [f32; 3] = [0.950_428_5, 1., 1.088_900_4];
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_rgb_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    [
+        0.412_390_8 * c[0] + 0.357_584_3 * c[1] + 0.180_480_8 * c[2],
+        0.212_639 * c[0] + 0.715_168_7 * c[1] + 0.072_192_3 * c[2],
+        0.019_330_8 * c[0] + 0.119_194_8 * c[1] + 0.950_532_2 * c[2],
+    ]
+}
+
+fn xyz_to_linear_rgb(c: [f32; 3]) -> [f32; 3] {
+    [
+        3.240_97 * c[0] - 1.537_383_2 * c[1] - 0.498_610_8 * c[2],
+        -0.969_243_6 * c[0] + 1.875_967_5 * c[1] + 0.041_555_1 * c[2],
+        0.055_630_1 * c[0] - 0.203_977 * c[1] + 1.056_971_5 * c[2],
+    ]
+}
+
+/// Opponent space in which FLIP filters the images like the eye does.
+fn xyz_to_ycxcz(c: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = (c[0] / D65[0], c[1] / D65[1], c[2] / D65[2]);
+    [116. * y - 16., 500. * (x - y), 200. * (y - z)]
+}
+
+fn ycxcz_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    let y = (c[0] + 16.) / 116.;
+    [
+        (c[1] / 500. + y) * D65[0],
+        y * D65[1],
+        (y - c[2] / 200.) * D65[2],
+    ]
+}
+
+fn xyz_to_lab(c: [f32; 3]) -> [f32; 3] {
+    let f = |t: f32| {
+        let delta: f32 = 6. / 29.;
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3. * delta * delta) + 4. / 29.
+        }
+    };
+    let (x, y, z) = (f(c[0] / D65[0]), f(c[1] / D65[1]), f(c[2] / D65[2]));
+    [116. * y - 16., 500. * (x - y), 200. * (y - z)]
+}
+
+/// Lab color with its chroma scaled by its lightness, after Hunt.
+fn hunt(lab: [f32; 3]) -> [f32; 3] {
+    [lab[0], 0.01 * lab[0] * lab[1], 0.01 * lab[0] * lab[2]]
+}
+
+fn hyab(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Per pixel error of the LDR FLIP metric, in [0, 1], for an observer seeing `ppd` pixels per
+/// degree.
+// https://research.nvidia.com/publication/2020-07_flip-difference-evaluator-alternating-images
+fn flip_map(reference: &[Rgb], test: &[Rgb], width: usize, height: usize, ppd: f32) -> Vec<f32> {
+    let (qc, pc, pt, qf) = (0.7, 0.4, 0.95, 0.5);
+    let ycxcz = |pixels: &[Rgb]| -> [Vec<f32>; 3] {
+        let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+        for pixel in pixels {
+            let d = pixel.to_display();
+            let linear = [
+                srgb_to_linear(d.r),
+                srgb_to_linear(d.g),
+                srgb_to_linear(d.b),
+            ];
+            let c = xyz_to_ycxcz(linear_rgb_to_xyz(linear));
+            for k in 0..3 {
+                channels[k].push(c[k]);
+            }
+        }
+        channels
+    };
+    let (reference, test) = (ycxcz(reference), ycxcz(test));
+
+    // Contrast sensitivity of the achromatic, red-green and blue-yellow channels as sums of
+    // two gaussians (a, b) over the visual angle.
+    let csf: [[(f32, f32); 2]; 3] = [
+        [(1., 0.0047), (0., 1e-5)],
+        [(1., 0.0053), (0., 1e-5)],
+        [(34.1, 0.04), (13.5, 0.025)],
+    ];
+    let radius =
+        (3. * (0.04f32 / (2. * std::f32::consts::PI.powi(2))).sqrt() * ppd).ceil() as usize;
+    let filter = |image: &[f32], terms: &[(f32, f32); 2]| -> Vec<f32> {
+        let mut filtered = vec![0.; image.len()];
+        let mut total = 0.;
+        for &(a, b) in terms.iter().filter(|(a, _)| *a != 0.) {
+            let pi = std::f32::consts::PI;
+            let kernel: Vec<f32> = (0..2 * radius + 1)
+                .map(|k| {
+                    let x = (k as f32 - radius as f32) / ppd;
+                    (-pi * pi * x * x / b).exp()
+                })
+                .collect();
+            let weight = a * pi / b;
+            let sum: f32 = kernel.iter().sum();
+            total += weight * sum * sum;
+            let blurred = convolve(image, width, height, &kernel, &kernel);
+            for (f, v) in filtered.iter_mut().zip(blurred) {
+                *f += weight * v;
+            }
+        }
+        filtered.iter().map(|f| f / total).collect()
+    };
+    // Filtered colors, clamped to the sRGB gamut, in Hunt adjusted Lab.
+    let perceived = |channels: &[Vec<f32>; 3]| -> Vec<[f32; 3]> {
+        let filtered: Vec<Vec<f32>> = (0..3).map(|k| filter(&channels[k], &csf[k])).collect();
+        (0..channels[0].len())
+            .map(|i| {
+                let rgb = xyz_to_linear_rgb(ycxcz_to_xyz([
+                    filtered[0][i],
+                    filtered[1][i],
+                    filtered[2][i],
+                ]));
+                let rgb = [
+                    rgb[0].clamp(0., 1.),
+                    rgb[1].clamp(0., 1.),
+                    rgb[2].clamp(0., 1.),
+                ];
+                hunt(xyz_to_lab(linear_rgb_to_xyz(rgb)))
+            })
+            .collect()
+    };
+    let (reference_lab, test_lab) = (perceived(&reference), perceived(&test));
+    let hunt_rgb = |rgb: [f32; 3]| hunt(xyz_to_lab(linear_rgb_to_xyz(rgb)));
+    let cmax = hyab(hunt_rgb([0., 1., 0.]), hunt_rgb([0., 0., 1.])).powf(qc);
+
+    // Edges and points of the luminance, found with the first and second derivatives of a
+    // gaussian, both normalized to positive and negative weights summing to 1 and -1.
+    let sd = 0.5 * 0.082 * ppd;
+    let feature_radius = (3. * sd).ceil() as usize;
+    let g: Vec<f32> = (0..2 * feature_radius + 1)
+        .map(|k| {
+            let x = k as f32 - feature_radius as f32;
+            (-x * x / (2. * sd * sd)).exp()
+        })
+        .collect();
+    let g_sum: f32 = g.iter().sum();
+    let g: Vec<f32> = g.iter().map(|w| w / g_sum).collect();
+    let normalize = |kernel: Vec<f32>| -> Vec<f32> {
+        let positive: f32 = kernel.iter().filter(|w| **w > 0.).sum();
+        let negative: f32 = -kernel.iter().filter(|w| **w < 0.).sum::<f32>();
+        kernel
+            .iter()
+            .map(|&w| if w > 0. { w / positive } else { w / negative })
+            .collect()
+    };
+    let offsets = (0..2 * feature_radius + 1).map(|k| k as f32 - feature_radius as f32);
+    let edge = normalize(offsets.clone().zip(&g).map(|(x, w)| -x * w).collect());
+    let point = normalize(
+        offsets
+            .zip(&g)
+            .map(|(x, w)| (x * x / (sd * sd) - 1.) * w)
+            .collect(),
+    );
+    let features = |channels: &[Vec<f32>; 3]| -> (Vec<f32>, Vec<f32>) {
+        let luminance: Vec<f32> = channels[0].iter().map(|y| (y + 16.) / 116.).collect();
+        let magnitude = |kernel: &[f32]| -> Vec<f32> {
+            let dx = convolve(&luminance, width, height, kernel, &g);
+            let dy = convolve(&luminance, width, height, &g, kernel);
+            dx.iter().zip(dy).map(|(x, y)| x.hypot(y)).collect()
+        };
+        (magnitude(&edge), magnitude(&point))
+    };
+    let (reference_edges, reference_points) = features(&reference);
+    let (test_edges, test_points) = features(&test);
+
+    (0..reference_lab.len())
+        .map(|i| {
+            let color = hyab(reference_lab[i], test_lab[i]).powf(qc);
+            let color = if color < pc * cmax {
+                pt / (pc * cmax) * color
+            } else {
+                pt + (color - pc * cmax) / (cmax - pc * cmax) * (1. - pt)
+            };
+            let feature = (std::f32::consts::FRAC_1_SQRT_2
+                * (reference_edges[i] - test_edges[i])
+                    .abs()
+                    .max((reference_points[i] - test_points[i]).abs()))
+            .powf(qf);
+            color.min(1.).powf(1. - feature)
+        })
+        .collect()
+}
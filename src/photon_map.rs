@@ -0,0 +1,487 @@
+//! Photon mapping after Jensen, "Realistic Image Synthesis Using Photon Mapping". Photons
+//! leave the point lights and are stored at the surfaces with smooth lobes in two maps:
+//! the caustic map holds the paths made of mirror and refraction bounces only, the global
+//! map the paths with at least one smooth bounce. Direct lighting is computed by shadow rays.
+//! Emissive surfaces only light the scene through final gathering.
+
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+
+use crate::bsdf::{sample_uniform_cone, sample_uniform_sphere, Bsdf};
+use crate::camera::Camera;
+use crate::color::Rgb;
+use crate::sampler::Rng;
+use crate::scene::{offset_origin, scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Emission stops after this many photons per requested photon even when the map is not full,
+/// in case the scene has nothing for the photons to land on.
+const MAX_EMITTED_PER_PHOTON: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PhotonSettings {
+    pub global_photons: usize,
+    pub caustic_photons: usize,
+    /// Radius of the disc gathering photons around a shaded point.
+    pub radius: f32,
+    /// Number of rays sampling the global map from the first smooth hit, the map is looked up
+    /// directly when 0.
+    pub final_gather: u32,
+    /// Progressive photon mapping: the maps are traced again for every pass with a shrinking
+    /// radius and the passes are averaged.
+    pub passes: Option<u32>,
+}
+
+impl Default for PhotonSettings {
+    fn default() -> PhotonSettings {
+        PhotonSettings {
+            global_photons: 200_000,
+            caustic_photons: 100_000,
+            radius: 0.2,
+            final_gather: 16,
+            passes: None,
+        }
+    }
+}
+
+impl PhotonSettings {
+    /// Gather radius of progressive pass `pass`, following Knaus and Zwicker, "Progressive
+    /// Photon Mapping: A Probabilistic Approach".
+    pub fn pass_radius(&self, pass: u32) -> f32 {
+        const ALPHA: f32 = 2. / 3.;
+        (0..pass).fold(self.radius, |radius, i| {
+            radius * ((i as f32 + ALPHA) / (i as f32 + 1.)).sqrt()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    position: Vec3f32,
+    /// Direction toward where the photon came from.
+    wi: Vec3f32,
+    power: Rgb,
+    /// Splitting axis of the kd-tree node.
+    axis: u8,
+}
+
+fn component(v: &Vec3f32, axis: u8) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+/// Balanced kd-tree stored in place: the node of a range is its median photon and the
+/// children are the ranges on each side.
+#[derive(Debug)]
+struct PhotonMap {
+    photons: Vec<Photon>,
+}
+
+impl PhotonMap {
+    fn build(mut photons: Vec<Photon>, emitted: usize) -> PhotonMap {
+        let scale = 1. / emitted.max(1) as f32;
+        for photon in &mut photons {
+            photon.power = photon.power * scale;
+        }
+        PhotonMap::balance(&mut photons);
+        PhotonMap { photons }
+    }
+
+    fn balance(photons: &mut [Photon]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let (mut min, mut max) = (photons[0].position, photons[0].position);
+        for photon in photons.iter() {
+            let p = photon.position;
+            min = Vec3f32::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3f32::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        let extent = max - min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| {
+            component(&a.position, axis)
+                .partial_cmp(&component(&b.position, axis))
+                .unwrap_or(Ordering::Equal)
+        });
+        photons[mid].axis = axis;
+        let (left, right) = photons.split_at_mut(mid);
+        PhotonMap::balance(left);
+        PhotonMap::balance(&mut right[1..]);
+    }
+
+    /// Calls `f` for every photon closer than `sqrt(radius2)` to `p`.
+    fn lookup<F: FnMut(&Photon)>(photons: &[Photon], p: &Vec3f32, radius2: f32, f: &mut F) {
+        if photons.is_empty() {
+            return;
+        }
+        let mid = photons.len() / 2;
+        let photon = &photons[mid];
+        let d = p - photon.position;
+        if d.dot_product(&d) <= radius2 {
+            f(photon);
+        }
+        let delta = component(p, photon.axis) - component(&photon.position, photon.axis);
+        let (near, far) = if delta < 0. {
+            (&photons[..mid], &photons[mid + 1..])
+        } else {
+            (&photons[mid + 1..], &photons[..mid])
+        };
+        PhotonMap::lookup(near, p, radius2, f);
+        if delta * delta <= radius2 {
+            PhotonMap::lookup(far, p, radius2, f);
+        }
+    }
+
+    /// Radiance reflected toward `wo` by the diffuse lobe, estimated from the photon density
+    /// around `p`. Glossy lobes would turn single photons into visible speckles.
+    fn radiance(&self, bsdf: &Bsdf, p: &Vec3f32, wo: &Vec3f32, radius: f32) -> Rgb {
+        let mut sum = Rgb::new(0., 0., 0.);
+        PhotonMap::lookup(&self.photons, p, radius * radius, &mut |photon| {
+            sum = sum + bsdf.f_diffuse(wo, &photon.wi) * photon.power;
+        });
+        sum / (PI * radius * radius)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MapKind {
+    Global,
+    Caustic,
+}
+
+fn intersect(
+    scene: &Scene,
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+) -> Option<(Vec3f32, Vec3f32, Material)> {
+    let mut hit = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+    if scene_intersect(orig, dir, time, scene, &mut hit, &mut n, &mut material) {
+        Some((hit, n, material))
+    } else {
+        None
+    }
+}
+
+/// Follows a photon through the scene, storing it in `photons` where `kind` wants it.
+#[allow(clippy::too_many_arguments)]
+fn trace_photon(
+    scene: &Scene,
+    rng: &mut Rng,
+    time: f32,
+    ray: (Vec3f32, Vec3f32),
+    power: Rgb,
+    max_depth: usize,
+    kind: MapKind,
+    photons: &mut Vec<Photon>,
+) {
+    let ((mut orig, mut dir), mut power) = (ray, power);
+    let mut specular_path = true;
+    for depth in 0..=max_depth {
+        let (hit, n, material) = match intersect(scene, &orig, &dir, time) {
+            Some(hit) => hit,
+            None => return,
+        };
+        let bsdf = Bsdf::new(&material, n);
+        let wo = dir * -1.;
+        if bsdf.has_smooth_lobes() && depth > 0 {
+            let store = match kind {
+                MapKind::Global => !specular_path,
+                MapKind::Caustic => specular_path,
+            };
+            if store {
+                photons.push(Photon {
+                    position: hit,
+                    wi: wo,
+                    power,
+                    axis: 0,
+                });
+            }
+        }
+
+        let sample = match bsdf.sample(&wo, [rng.next_f32(), rng.next_f32(), rng.next_f32()]) {
+            Some(sample) => sample,
+            None => return,
+        };
+        specular_path &= sample.delta;
+        if kind == MapKind::Caustic && !specular_path {
+            return;
+        }
+        // Russian roulette keeps the photon powers close to each other.
+        let scattered = power * sample.f * (sample.wi.dot_product(&n).abs() / sample.pdf);
+        let survival = (scattered.luminance() / power.luminance().max(1e-6)).min(1.);
+        if survival <= 0. || rng.next_f32() >= survival {
+            return;
+        }
+        power = scattered / survival;
+        orig = offset_origin(&hit, &n, &sample.wi);
+        dir = sample.wi;
+    }
+}
+
+/// Spheres with a mirror or refraction lobe, the targets of the caustic photons.
+fn specular_spheres(scene: &Scene, time: f32) -> Vec<(Vec3f32, f32)> {
+    scene
+        .spheres
+        .iter()
+        .filter(|s| s.material.albedo.b > 0. || s.material.albedo.a > 0.)
+        .map(|s| (s.center_at(time), s.radius))
+        .collect()
+}
+
+/// Cosine of the half angle of the cone from `p` around the sphere, `None` inside of it.
+fn cone_cos_max(p: &Vec3f32, sphere: &(Vec3f32, f32)) -> Option<f32> {
+    let d = sphere.0 - p;
+    let dist2 = d.dot_product(&d);
+    let radius2 = sphere.1 * sphere.1;
+    if dist2 <= radius2 {
+        None
+    } else {
+        Some((1. - radius2 / dist2).sqrt())
+    }
+}
+
+/// Direction from `p` toward one of `spheres` and its density over the union of the cones.
+fn sample_spheres(
+    rng: &mut Rng,
+    p: &Vec3f32,
+    spheres: &[(Vec3f32, f32)],
+) -> Option<(Vec3f32, f32)> {
+    let index = ((rng.next_f32() * spheres.len() as f32) as usize).min(spheres.len() - 1);
+    let cos_max = cone_cos_max(p, &spheres[index])?;
+    let mut axis = spheres[index].0 - p;
+    axis.normalize();
+    let dir = sample_uniform_cone(&axis, cos_max, rng.next_f32(), rng.next_f32());
+
+    let mut pdf = 0.;
+    for sphere in spheres {
+        if let Some(cos_max) = cone_cos_max(p, sphere) {
+            let mut axis = sphere.0 - p;
+            axis.normalize();
+            if dir.dot_product(&axis) >= cos_max {
+                pdf += 1. / (2. * PI * (1. - cos_max) * spheres.len() as f32);
+            }
+        }
+    }
+    Some((dir, pdf))
+}
+
+/// Caustic and global photon maps of a scene.
+#[derive(Debug)]
+pub struct PhotonMaps {
+    global: PhotonMap,
+    caustic: PhotonMap,
+    radius: f32,
+    final_gather: u32,
+}
+
+impl PhotonMaps {
+    /// Traces the photons of both maps, `seed` selects the random sequence so progressive
+    /// passes get different photons.
+    pub fn emit(
+        scene: &Scene,
+        camera: &Camera,
+        settings: &PhotonSettings,
+        radius: f32,
+        max_depth: usize,
+        seed: u64,
+    ) -> PhotonMaps {
+        let mut rng = Rng::new(seed);
+        let mut emit_map = |kind: MapKind, count: usize| {
+            let mut photons = Vec::with_capacity(count);
+            let mut emitted = 0;
+            if scene.lights.is_empty() {
+                return PhotonMap::build(photons, emitted);
+            }
+            let light_pdf = 1. / scene.lights.len() as f32;
+            while photons.len() < count && emitted < count * MAX_EMITTED_PER_PHOTON {
+                emitted += 1;
+                let light = &scene.lights[((rng.next_f32() * scene.lights.len() as f32) as usize)
+                    .min(scene.lights.len() - 1)];
+                let time = camera.sample_time(rng.next_f32());
+                let (dir, pdf_dir) = match kind {
+                    MapKind::Global => (
+                        sample_uniform_sphere(rng.next_f32(), rng.next_f32()),
+                        1. / (4. * PI),
+                    ),
+                    MapKind::Caustic => {
+                        let spheres = specular_spheres(scene, time);
+                        if spheres.is_empty() {
+                            break;
+                        }
+                        match sample_spheres(&mut rng, &light.position, &spheres) {
+                            Some(sample) => sample,
+                            None => continue,
+                        }
+                    }
+                };
+                let power =
+                    Rgb::new(1., 1., 1.) * (light.radiant_intensity() / (light_pdf * pdf_dir));
+                let ray = (light.position, dir);
+                trace_photon(
+                    scene,
+                    &mut rng,
+                    time,
+                    ray,
+                    power,
+                    max_depth,
+                    kind,
+                    &mut photons,
+                );
+            }
+            PhotonMap::build(photons, emitted)
+        };
+        let global = emit_map(MapKind::Global, settings.global_photons);
+        let caustic = emit_map(MapKind::Caustic, settings.caustic_photons);
+        PhotonMaps {
+            global,
+            caustic,
+            radius,
+            final_gather: settings.final_gather,
+        }
+    }
+
+    /// Light reflected at `p` straight from the point lights, by the diffuse lobe only when
+    /// `diffuse_only` is set.
+    #[allow(clippy::too_many_arguments)]
+    fn direct(
+        &self,
+        scene: &Scene,
+        bsdf: &Bsdf,
+        p: &Vec3f32,
+        n: &Vec3f32,
+        wo: &Vec3f32,
+        time: f32,
+        diffuse_only: bool,
+    ) -> Rgb {
+        let mut sum = Rgb::new(0., 0., 0.);
+        for light in &scene.lights {
+            let mut wi = light.position - p;
+            let dist2 = wi.dot_product(&wi);
+            wi.normalize();
+            let f = if diffuse_only {
+                bsdf.f_diffuse(wo, &wi)
+            } else {
+                bsdf.f(wo, &wi)
+            };
+            if f.r <= 0. && f.g <= 0. && f.b <= 0. {
+                continue;
+            }
+            let orig = offset_origin(p, n, &wi);
+            stats::count(Counter::ShadowRays, 1);
+            if let Some((hit, _, _)) = intersect(scene, &orig, &wi, time) {
+                let d = hit - orig;
+                if d.dot_product(&d) < dist2 {
+                    continue;
+                }
+            }
+            sum = sum + f * (wi.dot_product(n).abs() * light.radiant_intensity() / dist2);
+        }
+        sum
+    }
+
+    /// Outgoing radiance of a smooth surface without final gathering. Final gather rays only
+    /// look at the diffuse reflection, glossy highlights seen through them are mostly noise.
+    #[allow(clippy::too_many_arguments)]
+    fn smooth_radiance(
+        &self,
+        scene: &Scene,
+        bsdf: &Bsdf,
+        p: &Vec3f32,
+        n: &Vec3f32,
+        wo: &Vec3f32,
+        time: f32,
+        diffuse_only: bool,
+    ) -> Rgb {
+        self.direct(scene, bsdf, p, n, wo, time, diffuse_only)
+            + self.caustic.radiance(bsdf, p, wo, self.radius)
+            + self.global.radiance(bsdf, p, wo, self.radius)
+    }
+
+    /// Radiance along the ray (`orig`, `dir`), mirror and refraction bounces are followed up to
+    /// `max_depth` times.
+    #[allow(clippy::too_many_arguments)]
+    pub fn radiance(
+        &self,
+        scene: &Scene,
+        orig: &Vec3f32,
+        dir: &Vec3f32,
+        time: f32,
+        rng: &mut Rng,
+        depth: usize,
+        max_depth: usize,
+    ) -> Rgb {
+        let (hit, n, material) = match intersect(scene, orig, dir, time) {
+            Some(hit) => hit,
+            None => return scene.environment(dir),
+        };
+        stats::record_depth(depth + 1);
+        let bsdf = Bsdf::new(&material, n);
+        let wo = dir * -1.;
+        let mut l = material.emitted(scene, &n, dir);
+
+        if bsdf.has_smooth_lobes() {
+            if self.final_gather == 0 {
+                l = l + self.smooth_radiance(scene, &bsdf, &hit, &n, &wo, time, false);
+            } else {
+                l = l
+                    + self.direct(scene, &bsdf, &hit, &n, &wo, time, false)
+                    + self.caustic.radiance(&bsdf, &hit, &wo, self.radius);
+                let mut indirect = Rgb::new(0., 0., 0.);
+                for _ in 0..self.final_gather {
+                    let u = [rng.next_f32(), rng.next_f32(), rng.next_f32()];
+                    let sample = match bsdf.sample(&wo, u) {
+                        Some(sample) if !sample.delta => sample,
+                        _ => continue,
+                    };
+                    let weight = sample.f * (sample.wi.dot_product(&n).abs() / sample.pdf);
+                    let gather_orig = offset_origin(&hit, &n, &sample.wi);
+                    indirect = indirect
+                        + weight
+                            * match intersect(scene, &gather_orig, &sample.wi, time) {
+                                Some((p, gather_n, material)) => {
+                                    let gather_bsdf = Bsdf::new(&material, gather_n);
+                                    let gather_wo = sample.wi * -1.;
+                                    material.emitted(scene, &gather_n, &sample.wi)
+                                        + self.smooth_radiance(
+                                            scene,
+                                            &gather_bsdf,
+                                            &p,
+                                            &gather_n,
+                                            &gather_wo,
+                                            time,
+                                            true,
+                                        )
+                                }
+                                None => scene.environment(&sample.wi),
+                            };
+                }
+                l = l + indirect / self.final_gather as f32;
+            }
+        }
+
+        // Both the mirror and refraction lobes are followed, as in `cast_ray`.
+        if depth < max_depth {
+            for &(wi, weight) in bsdf.delta_lobes(&wo).iter().flatten() {
+                let next_orig = offset_origin(&hit, &n, &wi);
+                let next = self.radiance(scene, &next_orig, &wi, time, rng, depth + 1, max_depth);
+                l = l + next * weight;
+            }
+        }
+        l
+    }
+}
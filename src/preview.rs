@@ -0,0 +1,154 @@
+//! Live preview of the render in the terminal, for machines without an image viewer. Every
+//! character cell shows two pixels, the upper half block `▀` drawn in the color of the top
+//! one over a background of the color of the bottom one, with 24-bit ANSI escapes.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::color::Rgb;
+use crate::tile::Tile;
+use crate::to_rgb8;
+
+/// Time between two redraws while tiles come in, the image is also drawn after every pass.
+const REFRESH: Duration = Duration::from_millis(250);
+
+struct Framebuffer {
+    pixels: Vec<Rgb>,
+    /// Pixels received since the image was last complete.
+    received: usize,
+    last_draw: Option<Instant>,
+}
+
+pub struct Preview {
+    width: usize,
+    height: usize,
+    /// Size of the downscaled image, in pixels.
+    columns: usize,
+    rows: usize,
+    framebuffer: Mutex<Framebuffer>,
+}
+
+impl Preview {
+    /// Preview of a `width` by `height` image fitting the terminal, with a line left below
+    /// it for the progress bar.
+    pub fn new(width: usize, height: usize) -> Preview {
+        let (terminal_columns, terminal_lines) = terminal_size();
+        let scale = (terminal_columns as f32 / width as f32)
+            .min(2. * terminal_lines.saturating_sub(1).max(1) as f32 / height as f32)
+            .min(1.);
+        eprint!("\x1b[2J\x1b[?25l");
+        Preview {
+            width,
+            height,
+            columns: ((width as f32 * scale) as usize).max(1),
+            rows: ((height as f32 * scale) as usize).max(1),
+            framebuffer: Mutex::new(Framebuffer {
+                pixels: vec![Rgb::new(0., 0., 0.); width * height],
+                received: 0,
+                last_draw: None,
+            }),
+        }
+    }
+
+    /// Copies the `colors` of `tile` and redraws if it is time to.
+    pub fn update(&self, tile: &Tile, colors: &[Rgb]) {
+        let mut framebuffer = self.framebuffer.lock().unwrap();
+        for (index, color) in tile.pixels(self.width).zip(colors) {
+            framebuffer.pixels[index] = *color;
+        }
+        framebuffer.received += colors.len();
+        let pass_done = framebuffer.received >= self.width * self.height;
+        if pass_done {
+            framebuffer.received = 0;
+        }
+        if pass_done || framebuffer.last_draw.is_none_or(|t| t.elapsed() >= REFRESH) {
+            framebuffer.last_draw = Some(Instant::now());
+            self.draw(&framebuffer.pixels);
+        }
+    }
+
+    /// Draws the last state of the image and gives the terminal back.
+    pub fn finish(&self) {
+        self.draw(&self.framebuffer.lock().unwrap().pixels);
+        eprint!("\x1b[?25h");
+    }
+
+    fn draw(&self, pixels: &[Rgb]) {
+        let small = downscale(pixels, self.width, self.height, self.columns, self.rows);
+        let bytes = to_rgb8(&small);
+        let color = |x: usize, y: usize| -> Option<&[u8]> {
+            let index = 3 * (y * self.columns + x);
+            (y < self.rows).then(|| &bytes[index..index + 3])
+        };
+        let mut out = String::from("\x1b[H");
+        for y in (0..self.rows).step_by(2) {
+            for x in 0..self.columns {
+                let top = color(x, y).unwrap();
+                out.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                match color(x, y + 1) {
+                    Some(bottom) => out.push_str(&format!(
+                        "\x1b[48;2;{};{};{}m",
+                        bottom[0], bottom[1], bottom[2]
+                    )),
+                    None => out.push_str("\x1b[49m"),
+                }
+                out.push('▀');
+            }
+            out.push_str("\x1b[0m\r\n");
+        }
+        let mut stderr = io::stderr();
+        stderr.write_all(out.as_bytes()).ok();
+        stderr.flush().ok();
+    }
+}
+
+/// Averages the pixels of a `width` by `height` image over the ones of a smaller image.
+fn downscale(pixels: &[Rgb], width: usize, height: usize, columns: usize, rows: usize) -> Vec<Rgb> {
+    let mut small = Vec::with_capacity(columns * rows);
+    for y in 0..rows {
+        let (y0, y1) = (
+            y * height / rows,
+            ((y + 1) * height / rows).max(y * height / rows + 1),
+        );
+        for x in 0..columns {
+            let (x0, x1) = (
+                x * width / columns,
+                ((x + 1) * width / columns).max(x * width / columns + 1),
+            );
+            let mut sum = Rgb::new(0., 0., 0.);
+            for row in pixels[y0 * width..y1 * width].chunks(width) {
+                for pixel in &row[x0..x1] {
+                    sum = sum + *pixel;
+                }
+            }
+            small.push(sum / ((x1 - x0) * (y1 - y0)) as f32);
+        }
+    }
+    small
+}
+
+/// Columns and lines of the terminal, from the environment or `stty`, 80 by 24 otherwise.
+fn terminal_size() -> (usize, usize) {
+    let variable = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+    if let (Some(columns), Some(lines)) = (variable("COLUMNS"), variable("LINES")) {
+        return (columns, lines);
+    }
+    // https://man7.org/linux/man-pages/man1/stty.1.html, `size` prints the lines first.
+    let size = File::open("/dev/tty")
+        .and_then(|tty| Command::new("stty").arg("size").stdin(tty).output())
+        .ok()
+        .and_then(|output| {
+            let text = String::from_utf8(output.stdout).ok()?;
+            let mut values = text.split_whitespace().map(|v| v.parse::<usize>().ok());
+            let lines = values.next()??;
+            let columns = values.next()??;
+            Some((columns, lines))
+        });
+    match size {
+        Some((columns, lines)) if columns > 0 && lines > 0 => (columns, lines),
+        _ => (80, 24),
+    }
+}
@@ -0,0 +1,101 @@
+//! Progress bar drawn on the standard error while the tiles of a render come in.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::stats::{self, Counter};
+
+const BAR_WIDTH: usize = 30;
+
+/// Time between two redraws of the bar.
+const REFRESH: Duration = Duration::from_millis(100);
+
+pub struct Progress {
+    label: String,
+    /// Pixels to render, over every pass and eye.
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+    start_rays: u64,
+    last_draw: Mutex<Option<Instant>>,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: usize, enabled: bool) -> Progress {
+        Progress {
+            label: label.to_string(),
+            total: total.max(1),
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+            start_rays: stats::snapshot().get(Counter::Rays),
+            last_draw: Mutex::new(None),
+            enabled,
+        }
+    }
+
+    /// Counts `pixels` more rendered pixels.
+    pub fn advance(&self, pixels: usize) {
+        let done = self.done.fetch_add(pixels, Ordering::Relaxed) + pixels;
+        if !self.enabled {
+            return;
+        }
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if last_draw.is_some_and(|t| t.elapsed() < REFRESH) && done < self.total {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+        self.draw(done);
+    }
+
+    /// Part of the pixels rendered so far.
+    pub fn fraction(&self) -> f64 {
+        (self.done.load(Ordering::Relaxed) as f64 / self.total as f64).min(1.)
+    }
+
+    fn draw(&self, done: usize) {
+        let fraction = (done as f64 / self.total as f64).min(1.);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rays = stats::snapshot().get(Counter::Rays) - self.start_rays;
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let eta = if fraction > 0. {
+            format_duration(elapsed * (1. - fraction) / fraction)
+        } else {
+            String::from("--:--")
+        };
+        eprint!(
+            "\r{} [{}{}] {:5.1}% {:7.2} Mrays/s ETA {}",
+            self.label,
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            100. * fraction,
+            rays as f64 / elapsed.max(1e-9) / 1e6,
+            eta
+        );
+        io::stderr().flush().ok();
+    }
+
+    /// Leaves the line of the bar.
+    pub fn finish(&self) {
+        if self.enabled {
+            self.draw(self.done.load(Ordering::Relaxed));
+            eprintln!();
+        }
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
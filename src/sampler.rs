@@ -0,0 +1,137 @@
+use crate::color::Rgb;
+
+/// Small PCG32 generator, seeded per pixel so renders stay deterministic across threads.
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // https://www.pcg-random.org/download.html
+        let mut rng = Rng {
+            state: 0,
+            inc: (seed << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(0x853c_49e6_748f_ea9b);
+        rng.next_u32();
+        rng
+    }
+
+    #[inline(always)]
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Uniform float in [0, 1).
+    #[inline(always)]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 * (1. / (1 << 24) as f32)
+    }
+
+    /// Raw state, to carry the generator over to another run.
+    pub fn to_words(self) -> [u64; 2] {
+        [self.state, self.inc]
+    }
+
+    pub fn from_words(words: [u64; 2]) -> Rng {
+        Rng {
+            state: words[0],
+            inc: words[1],
+        }
+    }
+}
+
+/// Running mean and variance of the samples of one pixel (Welford's algorithm).
+#[derive(Debug, Clone, Copy)]
+pub struct PixelStats {
+    count: u32,
+    mean: Rgb,
+    mean_luminance: f32,
+    m2_luminance: f32,
+}
+
+impl PixelStats {
+    pub fn new() -> PixelStats {
+        PixelStats {
+            count: 0,
+            mean: Rgb::new(0., 0., 0.),
+            mean_luminance: 0.,
+            m2_luminance: 0.,
+        }
+    }
+
+    pub fn add(&mut self, sample: Rgb) {
+        self.count += 1;
+        let n = self.count as f32;
+        self.mean = self.mean + (sample - self.mean) / n;
+        let luminance = sample.luminance();
+        let delta = luminance - self.mean_luminance;
+        self.mean_luminance += delta / n;
+        self.m2_luminance += delta * (luminance - self.mean_luminance);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Rgb {
+        self.mean
+    }
+
+    /// Raw state as bits, to carry the statistics over to another run.
+    pub fn to_words(self) -> [u32; 6] {
+        [
+            self.count,
+            self.mean.r.to_bits(),
+            self.mean.g.to_bits(),
+            self.mean.b.to_bits(),
+            self.mean_luminance.to_bits(),
+            self.m2_luminance.to_bits(),
+        ]
+    }
+
+    pub fn from_words(words: [u32; 6]) -> PixelStats {
+        let f = |k: usize| f32::from_bits(words[k]);
+        PixelStats {
+            count: words[0],
+            mean: Rgb::new(f(1), f(2), f(3)),
+            mean_luminance: f(4),
+            m2_luminance: f(5),
+        }
+    }
+
+    /// Standard error of the mean luminance relative to that mean.
+    pub fn relative_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::MAX;
+        }
+        let n = self.count as f32;
+        let variance = self.m2_luminance / (n - 1.);
+        (variance / n).sqrt() / self.mean_luminance.max(1e-2)
+    }
+}
+
+/// Sampling state of one pixel, which can stop between two samples and go on later.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelState {
+    pub rng: Rng,
+    pub stats: PixelStats,
+}
+
+impl PixelState {
+    pub fn new(seed: u64) -> PixelState {
+        PixelState {
+            rng: Rng::new(seed),
+            stats: PixelStats::new(),
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::hdr::HDRDecoder;
+use image::RgbImage;
+
+use crate::ambient_occlusion::AoSettings;
+use crate::bvh::{Aabb, Bvh};
+use crate::color::{Rgb, Rgba};
+use crate::emitter::Emission;
+use crate::env_light::EnvLight;
+use crate::medium::Medium;
+use crate::sky::Sun;
+use crate::spectrum::Dispersion;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+use crate::RayTracerError;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+    pub refractive_index: f32,
+    pub albedo: Rgba,
+    pub diffuse_color: Rgb,
+    pub specular_exponent: f32,
+    /// Index in `Scene::media` of the medium filling the inside of the objects.
+    pub medium: Option<usize>,
+    /// Replaces `refractive_index` in spectral mode.
+    pub dispersion: Option<Dispersion>,
+    pub emission: Option<Emission>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            refractive_index: 1.,
+            albedo: Rgba::new(1., 0., 0., 0.),
+            diffuse_color: Rgb::new(0., 0., 0.),
+            specular_exponent: 0.,
+            medium: None,
+            dispersion: None,
+            emission: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn refractive_index_at(&self, lambda_nm: f32) -> f32 {
+        self.dispersion
+            .map_or(self.refractive_index, |d| d.refractive_index(lambda_nm))
+    }
+
+    /// Radiance emitted toward the origin of a ray going in direction `dir`, which hits the
+    /// surface with outward normal `n`.
+    pub fn emitted(&self, scene: &Scene, n: &Vec3f32, dir: &Vec3f32) -> Rgb {
+        match self.emission {
+            Some(ref emission) if dir.dot_product(n) < 0. => emission.radiance(scene, n),
+            _ => Rgb::new(0., 0., 0.),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Sphere {
+    pub center: Vec3f32,
+    pub radius: f32,
+    pub material: Material,
+    /// Displacement per unit of time, the sphere is at `center` at time 0.
+    pub velocity: Vec3f32,
+    /// Identifies the spheres sharing a material in the material ID pass.
+    pub material_id: usize,
+}
+
+#[derive(Debug)]
+pub struct Light {
+    pub position: Vec3f32,
+    pub intensity: f32,
+}
+
+impl Light {
+    /// Whitted shading has no distance falloff, physically based integrators use the radiant
+    /// intensity giving the same lighting at the world origin, where the default camera sits.
+    pub fn radiant_intensity(&self) -> f32 {
+        self.intensity * std::f32::consts::PI * self.position.dot_product(&self.position)
+    }
+}
+
+/// Equirectangular env map with linear values, LDR images give values in [0, 1].
+#[derive(Debug)]
+pub struct EnvMap {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl EnvMap {
+    /// Reads Radiance `.hdr` files with their full range, other formats through `image`.
+    pub fn load(path: &Path) -> Result<EnvMap, RayTracerError> {
+        let is_hdr = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if !is_hdr {
+            return Ok(EnvMap::from_image(&image::open(path)?.to_rgb()));
+        }
+        let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .iter()
+            .map(|p| Rgb::new(p[0], p[1], p[2]))
+            .collect();
+        Ok(EnvMap {
+            width: metadata.width as usize,
+            height: metadata.height as usize,
+            pixels,
+        })
+    }
+
+    pub fn from_image(image: &RgbImage) -> EnvMap {
+        EnvMap {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image.pixels().map(|p| Rgb::from_rgb8(p.data)).collect(),
+        }
+    }
+
+    /// Map coordinates in [0, 1] of the direction `dir`.
+    pub fn coordinates(dir: &Vec3f32) -> (f32, f32) {
+        let mut norm_dir = *dir;
+        norm_dir.normalize();
+        (
+            norm_dir.z.atan2(norm_dir.x) / (2. * std::f32::consts::PI) + 0.5,
+            norm_dir.y.acos() / std::f32::consts::PI,
+        )
+    }
+
+    /// Direction of the map coordinates (`u`, `v`), the inverse of `coordinates`.
+    pub fn direction(u: f32, v: f32) -> Vec3f32 {
+        let phi = (u - 0.5) * 2. * std::f32::consts::PI;
+        let theta = v * std::f32::consts::PI;
+        Vec3f32::new(
+            phi.cos() * theta.sin(),
+            theta.cos(),
+            phi.sin() * theta.sin(),
+        )
+    }
+
+    pub fn lookup(&self, dir: &Vec3f32) -> Rgb {
+        let (u, v) = EnvMap::coordinates(dir);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+#[derive(Debug)]
+pub struct Scene {
+    pub spheres: Vec<Sphere>,
+    pub lights: Vec<Light>,
+    pub background: EnvMap,
+    /// Importance sampling of `background` for direct lighting, off by default.
+    pub env_light: Option<EnvLight>,
+    /// Sun of a physical sky, seen in front of `background`.
+    pub sun: Option<Sun>,
+    /// Indices of the spheres with an emissive material.
+    pub emitters: Vec<usize>,
+    /// Images of the emission textures.
+    pub textures: Vec<EnvMap>,
+    /// Ambient occlusion multiplied into the diffuse lighting of Whitted shading.
+    pub ambient_occlusion: Option<AoSettings>,
+    pub media: Vec<Medium>,
+    /// Medium filling the space outside of all objects, where the camera is.
+    pub fog: Option<usize>,
+    /// Distance through the fog of the rays escaping to the env map.
+    pub fog_distance: f32,
+    bvh: Bvh,
+}
+
+impl Scene {
+    /// The acceleration structure bounds every sphere over the `shutter` time interval.
+    pub fn new(
+        spheres: Vec<Sphere>,
+        lights: Vec<Light>,
+        background: EnvMap,
+        shutter: (f32, f32),
+    ) -> Scene {
+        let emitters = spheres
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.material.emission.is_some())
+            .map(|(i, _)| i)
+            .collect();
+        let mut scene = Scene {
+            spheres,
+            lights,
+            background,
+            env_light: None,
+            sun: None,
+            emitters,
+            textures: Vec::new(),
+            ambient_occlusion: None,
+            media: Vec::new(),
+            fog: None,
+            fog_distance: f32::INFINITY,
+            bvh: Bvh::build(&[]),
+        };
+        scene.build_bvh(shutter);
+        scene
+    }
+
+    /// Radiance of the env map and the sun seen in direction `dir`.
+    pub fn environment(&self, dir: &Vec3f32) -> Rgb {
+        match self.sun {
+            Some(ref sun) => self.background.lookup(dir) + sun.disk(dir),
+            None => self.background.lookup(dir),
+        }
+    }
+
+    /// Must be called again whenever the spheres move or the shutter interval changes.
+    pub fn build_bvh(&mut self, shutter: (f32, f32)) {
+        let bounds: Vec<Aabb> = self
+            .spheres
+            .iter()
+            .map(|s| s.bounds(shutter.0).union(&s.bounds(shutter.1)))
+            .collect();
+        self.bvh = Bvh::build(&bounds);
+    }
+}
+
+/// Ray origin moved off the surface at `p` with normal `n`, on the side of `dir`.
+pub fn offset_origin(p: &Vec3f32, n: &Vec3f32, dir: &Vec3f32) -> Vec3f32 {
+    if dir.dot_product(n) < 0. {
+        p - n * 1e-3
+    } else {
+        p + n * 1e-3
+    }
+}
+
+/// Object hit by a ray.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Object {
+    Sphere(usize),
+    Checkerboard,
+}
+
+impl Object {
+    /// Surface coordinates in [0, 1] at `point`, spheres are mapped like the env map and the
+    /// checkerboard is stretched over its extent.
+    pub fn uv(self, point: &Vec3f32, n: &Vec3f32) -> (f32, f32) {
+        match self {
+            Object::Sphere(_) => EnvMap::coordinates(n),
+            Object::Checkerboard => ((point.x + 10.) / 20., (point.z + 30.) / 20.),
+        }
+    }
+
+    /// Object and material IDs, 0 is left for the background and 1 is the checkerboard.
+    pub fn ids(self, scene: &Scene) -> (usize, usize) {
+        match self {
+            Object::Sphere(index) => (index + 2, scene.spheres[index].material_id + 2),
+            Object::Checkerboard => (1, 1),
+        }
+    }
+}
+
+pub fn scene_intersect(
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    scene: &Scene,
+    hit: &mut Vec3f32,
+    n: &mut Vec3f32,
+    material: &mut Material,
+) -> bool {
+    scene_intersect_object(orig, dir, time, scene, hit, n, material).is_some()
+}
+
+/// `scene_intersect` telling which object is hit.
+pub fn scene_intersect_object(
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    scene: &Scene,
+    hit: &mut Vec3f32,
+    n: &mut Vec3f32,
+    material: &mut Material,
+) -> Option<Object> {
+    let mut sphere_dist = f32::MAX;
+    let mut sphere_index = 0;
+    // The checkerboard is always tested.
+    let mut tests = 1;
+    scene.bvh.traverse(orig, dir, sphere_dist, |index| {
+        let s = &scene.spheres[index];
+        tests += 1;
+        let mut dist_i = 0.;
+        if s.ray_intersect(orig, dir, time, &mut dist_i) && dist_i < sphere_dist {
+            sphere_dist = dist_i;
+            sphere_index = index;
+            *hit = orig + dir * dist_i;
+            *n = *hit - s.center_at(time);
+            n.normalize();
+            *material = s.material;
+            return Some(dist_i);
+        }
+        None
+    });
+
+    stats::count(Counter::Rays, 1);
+    stats::count(Counter::IntersectionTests, tests);
+
+    let mut checkerboard_dist = f32::MAX;
+    if dir.y.abs() > 1e-3 {
+        let d = -(orig.y + 4.) / dir.y;
+        let pt = orig + dir * d;
+        if d > 0. && pt.x.abs() < 10. && pt.z < -10. && pt.z > -30. && d < sphere_dist {
+            checkerboard_dist = d;
+            *hit = pt;
+            *n = Vec3f32::new(0., 1., 0.);
+            let diffuse_color = if (((0.5 * hit.x + 1000.) as i32 + (0.5 * hit.z) as i32) & 1) != 0
+            {
+                Rgb::new(0.3, 0.3, 0.3)
+            } else {
+                Rgb::new(0.3, 0.2, 0.1)
+            };
+            *material = Material {
+                diffuse_color,
+                ..Material::default()
+            };
+        }
+    }
+
+    if sphere_dist.min(checkerboard_dist) >= 1000. {
+        None
+    } else if checkerboard_dist < sphere_dist {
+        Some(Object::Checkerboard)
+    } else {
+        Some(Object::Sphere(sphere_index))
+    }
+}
+
+impl Sphere {
+    pub fn new(center: Vec3f32, radius: f32, material: Material) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material,
+            velocity: Vec3f32::new(0., 0., 0.),
+            material_id: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn center_at(&self, time: f32) -> Vec3f32 {
+        self.center + self.velocity * time
+    }
+
+    fn bounds(&self, time: f32) -> Aabb {
+        let center = self.center_at(time);
+        Aabb::new(center - self.radius, center + self.radius)
+    }
+
+    fn ray_intersect(&self, orig: &Vec3f32, dir: &Vec3f32, time: f32, t0: &mut f32) -> bool {
+        let l = self.center_at(time) - orig;
+        let tca = l.dot_product(dir);
+        let d2 = l.dot_product(&l) - tca * tca;
+        if d2 > self.radius * self.radius {
+            return false;
+        }
+        let thc = (self.radius * self.radius - d2).sqrt();
+        *t0 = tca - thc;
+        let t1 = tca + thc;
+        if *t0 < 0.0 {
+            *t0 = t1;
+        }
+        if *t0 < 0.0 {
+            return false;
+        }
+        true
+    }
+}
@@ -0,0 +1,377 @@
+//! Line based scene description, see `scenes/animated.scene` for an example.
+//!
+//! ```text
+//! envmap <path>                         # relative to the scene file
+//! sky <turbidity> <ground albedo r g b> <sun>   # physical sky instead of the env map
+//! camera <x y z> <fov in degrees>
+//! medium <name> <absorption r g b> <scattering r g b> <g> [<grid> <min x y z> <max x y z>]
+//! fog <medium> [<distance>]             # medium around the objects, up to the env map
+//! material <name> <ior> <albedo r g b a> <diffuse r g b> <specular exponent> [<option>...]
+//! sphere <x y z> <radius> <material> [<velocity x y z>]
+//! light <x y z> <intensity>
+//! key <target> <frame> <step|linear|bezier> <value...>
+//! ```
+//!
+//! Key targets are `camera.position`, `sphere.<i>.center`, `light.<i>.position`,
+//! `light.<i>.intensity` and `material.<name>.color`, they refer to objects declared above.
+//! The sun of the sky is either `sun <azimuth> <elevation>` in degrees or
+//! `location <latitude> <longitude> <YYYY-MM-DD> <HH:MM> <UTC offset in hours>`.
+//! Material options are the name of a medium, `cauchy <a> <b>` or
+//! `sellmeier <b1 b2 b3> <c1 c2 c3>` dispersion coefficients for spectral mode, in micrometers,
+//! and `emission <r g b> <strength>` optionally followed by `texture <path>`, an image
+//! multiplying the emission and wrapped around the spheres like the env map.
+//! The medium of a material fills the inside of its spheres, the grid file of heterogeneous
+//! media is described in `medium.rs` and is stretched over the given box.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::animation::{Animation, Interpolation, Keyframe, Target};
+use crate::bvh::Aabb;
+use crate::camera::Camera;
+use crate::color::{Rgb, Rgba};
+use crate::emitter::Emission;
+use crate::medium::{DensityGrid, Medium};
+use crate::scene::{EnvMap, Light, Material, Scene, Sphere};
+use crate::sky::{self, parse_date, parse_time, SkySettings, SunPosition};
+use crate::spectrum::Dispersion;
+use crate::vec3::Vec3f32;
+use crate::RayTracerError;
+
+#[derive(Debug)]
+pub struct SceneFile {
+    pub scene: Scene,
+    pub animation: Animation,
+    /// Files the scene loaded: env map, textures and density grids.
+    pub assets: Vec<PathBuf>,
+}
+
+/// Path of the file `name` of a scene in `directory`.
+fn asset_path(directory: &Path, name: &str, confined: bool) -> Result<PathBuf, String> {
+    let escapes = Path::new(name)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if confined && escapes {
+        return Err(format!(
+            "{} is outside of the assets directory, paths must be relative without ..",
+            name
+        ));
+    }
+    Ok(directory.join(name))
+}
+
+pub fn load_scene(path: &Path, camera: &mut Camera) -> Result<SceneFile, RayTracerError> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| RayTracerError::Scene(format!("{}: {}", path.display(), err)))?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&source, path, directory, false, camera)
+}
+
+/// Reads the scene in `source`, whose relative paths start from `directory`. Errors refer to
+/// it as `path`. When `confined`, the paths must be relative and without `..`, so that only
+/// the files under `directory` can be read.
+pub fn parse_scene(
+    source: &str,
+    path: &Path,
+    directory: &Path,
+    confined: bool,
+    camera: &mut Camera,
+) -> Result<SceneFile, RayTracerError> {
+    let mut envmap = directory.join("envmap.jpg");
+    let mut assets = Vec::new();
+    let mut sky = None;
+    let mut media: Vec<Medium> = Vec::new();
+    let mut medium_names: HashMap<String, usize> = HashMap::new();
+    let mut fog = None;
+    let mut fog_distance = f32::INFINITY;
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    let mut material_ids: HashMap<String, usize> = HashMap::new();
+    let mut textures = Vec::new();
+    let mut sphere_materials: Vec<String> = Vec::new();
+    let mut spheres = Vec::new();
+    let mut lights = Vec::new();
+    let mut animation = Animation::default();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |msg: String| {
+            RayTracerError::Scene(format!("{}:{}: {}", path.display(), line_index + 1, msg))
+        };
+        let line = line.split('#').next().unwrap();
+        let mut tokens = Tokens {
+            tokens: line.split_whitespace().collect(),
+            next: 1,
+        };
+        let keyword = match tokens.tokens.first() {
+            Some(&keyword) => keyword,
+            None => continue,
+        };
+        match keyword {
+            "envmap" => {
+                envmap =
+                    asset_path(directory, tokens.word().map_err(error)?, confined).map_err(error)?
+            }
+            "sky" => {
+                let turbidity = tokens.number().map_err(error)?;
+                let ground_albedo = tokens.rgb().map_err(error)?;
+                let sun = match tokens.word().map_err(error)? {
+                    "sun" => SunPosition::Angles {
+                        azimuth: tokens.number().map_err(error)?,
+                        elevation: tokens.number().map_err(error)?,
+                    },
+                    "location" => SunPosition::Location {
+                        latitude: tokens.number().map_err(error)?,
+                        longitude: tokens.number().map_err(error)?,
+                        date: parse_date(tokens.word().map_err(error)?).map_err(error)?,
+                        hours: parse_time(tokens.word().map_err(error)?).map_err(error)?,
+                        utc_offset: tokens.number().map_err(error)?,
+                    },
+                    other => return Err(error(format!("unknown sun position {}", other))),
+                };
+                sky = Some(SkySettings {
+                    turbidity,
+                    ground_albedo,
+                    sun,
+                });
+            }
+            "camera" => {
+                camera.position = tokens.vec3().map_err(error)?;
+                camera.fov = tokens.number().map_err(error)?.to_radians();
+            }
+            "medium" => {
+                let name = tokens.word().map_err(error)?;
+                let mut medium = Medium {
+                    absorption: tokens.rgb().map_err(error)?,
+                    scattering: tokens.rgb().map_err(error)?,
+                    g: tokens.number().map_err(error)?,
+                    grid: None,
+                };
+                if !tokens.is_empty() {
+                    let grid_path = asset_path(directory, tokens.word().map_err(error)?, confined)
+                        .map_err(error)?;
+                    let bounds =
+                        Aabb::new(tokens.vec3().map_err(error)?, tokens.vec3().map_err(error)?);
+                    medium.grid = Some(DensityGrid::load(&grid_path, bounds).map_err(error)?);
+                    assets.push(grid_path);
+                }
+                medium_names.insert(name.to_string(), media.len());
+                media.push(medium);
+            }
+            "fog" => {
+                let name = tokens.word().map_err(error)?;
+                fog = Some(find_medium(&medium_names, name).map_err(error)?);
+                if !tokens.is_empty() {
+                    fog_distance = tokens.number().map_err(error)?;
+                }
+            }
+            "material" => {
+                let name = tokens.word().map_err(error)?;
+                let mut material = Material {
+                    refractive_index: tokens.number().map_err(error)?,
+                    albedo: Rgba::new(
+                        tokens.number().map_err(error)?,
+                        tokens.number().map_err(error)?,
+                        tokens.number().map_err(error)?,
+                        tokens.number().map_err(error)?,
+                    ),
+                    diffuse_color: tokens.rgb().map_err(error)?,
+                    specular_exponent: tokens.number().map_err(error)?,
+                    medium: None,
+                    dispersion: None,
+                    emission: None,
+                };
+                while !tokens.is_empty() {
+                    match tokens.word().map_err(error)? {
+                        "cauchy" => {
+                            material.dispersion = Some(Dispersion::Cauchy {
+                                a: tokens.number().map_err(error)?,
+                                b: tokens.number().map_err(error)?,
+                            })
+                        }
+                        "sellmeier" => {
+                            let b = tokens.vec3().map_err(error)?;
+                            let c = tokens.vec3().map_err(error)?;
+                            material.dispersion = Some(Dispersion::Sellmeier {
+                                b: [b.x, b.y, b.z],
+                                c: [c.x, c.y, c.z],
+                            })
+                        }
+                        "emission" => {
+                            material.emission = Some(Emission {
+                                color: tokens.rgb().map_err(error)?,
+                                strength: tokens.number().map_err(error)?,
+                                texture: None,
+                            })
+                        }
+                        "texture" => {
+                            let texture_path =
+                                asset_path(directory, tokens.word().map_err(error)?, confined)
+                                    .map_err(error)?;
+                            let emission = material.emission.as_mut().ok_or_else(|| {
+                                error(String::from("texture must follow an emission"))
+                            })?;
+                            let texture = EnvMap::load(&texture_path).map_err(|err| {
+                                error(format!("{}: {}", texture_path.display(), err))
+                            })?;
+                            emission.texture = Some(textures.len());
+                            textures.push(texture);
+                            assets.push(texture_path);
+                        }
+                        medium => {
+                            material.medium =
+                                Some(find_medium(&medium_names, medium).map_err(error)?)
+                        }
+                    }
+                }
+                materials.insert(name.to_string(), material);
+                let id = material_ids.len();
+                material_ids.entry(name.to_string()).or_insert(id);
+            }
+            "sphere" => {
+                let center = tokens.vec3().map_err(error)?;
+                let radius = tokens.number().map_err(error)?;
+                let name = tokens.word().map_err(error)?;
+                let material = *materials
+                    .get(name)
+                    .ok_or_else(|| error(format!("unknown material {}", name)))?;
+                let mut sphere = Sphere::new(center, radius, material);
+                sphere.material_id = material_ids[name];
+                if !tokens.is_empty() {
+                    sphere.velocity = tokens.vec3().map_err(error)?;
+                }
+                spheres.push(sphere);
+                sphere_materials.push(name.to_string());
+            }
+            "light" => lights.push(Light {
+                position: tokens.vec3().map_err(error)?,
+                intensity: tokens.number().map_err(error)?,
+            }),
+            "key" => {
+                let target_name = tokens.word().map_err(error)?;
+                let target = match target_name.split('.').collect::<Vec<_>>()[..] {
+                    ["camera", "position"] => Target::CameraPosition,
+                    ["sphere", i, "center"] => {
+                        Target::SphereCenter(parse_index(i, spheres.len()).map_err(error)?)
+                    }
+                    ["light", i, "position"] => {
+                        Target::LightPosition(parse_index(i, lights.len()).map_err(error)?)
+                    }
+                    ["light", i, "intensity"] => {
+                        Target::LightIntensity(parse_index(i, lights.len()).map_err(error)?)
+                    }
+                    ["material", name, "color"] => {
+                        if !materials.contains_key(name) {
+                            return Err(error(format!("unknown material {}", name)));
+                        }
+                        Target::MaterialColor(
+                            (0..spheres.len())
+                                .filter(|&i| sphere_materials[i] == name)
+                                .collect(),
+                        )
+                    }
+                    _ => return Err(error(format!("unknown key target {}", target_name))),
+                };
+                let frame = tokens.number().map_err(error)?;
+                let interpolation = match tokens.word().map_err(error)? {
+                    "step" => Interpolation::Step,
+                    "linear" => Interpolation::Linear,
+                    "bezier" => Interpolation::Bezier,
+                    other => return Err(error(format!("unknown interpolation {}", other))),
+                };
+                let value = match target {
+                    Target::LightIntensity(_) => [tokens.number().map_err(error)?, 0., 0.],
+                    _ => {
+                        let v = tokens.vec3().map_err(error)?;
+                        [v.x, v.y, v.z]
+                    }
+                };
+                let base_velocity = match target {
+                    Target::CameraPosition => camera.velocity,
+                    Target::SphereCenter(i) => spheres[i].velocity,
+                    _ => Vec3f32::new(0., 0., 0.),
+                };
+                let track = animation.track_mut(target);
+                track.base_velocity = base_velocity;
+                track.insert(Keyframe {
+                    frame,
+                    value,
+                    interpolation,
+                });
+            }
+            other => return Err(error(format!("unknown keyword {}", other))),
+        }
+        if !tokens.is_empty() {
+            return Err(error(String::from("too many values")));
+        }
+    }
+
+    let (background, sun) = match sky {
+        Some(ref settings) => sky::bake(settings),
+        None => {
+            let background = EnvMap::load(&envmap)?;
+            assets.push(envmap);
+            (background, None)
+        }
+    };
+    let shutter = (camera.shutter_open, camera.shutter_close);
+    let mut scene = Scene::new(spheres, lights, background, shutter);
+    scene.sun = sun;
+    scene.textures = textures;
+    scene.media = media;
+    scene.fog = fog;
+    scene.fog_distance = fog_distance;
+    Ok(SceneFile {
+        scene,
+        animation,
+        assets,
+    })
+}
+
+fn find_medium(names: &HashMap<String, usize>, name: &str) -> Result<usize, String> {
+    names
+        .get(name)
+        .copied()
+        .ok_or_else(|| format!("unknown medium {}", name))
+}
+
+fn parse_index(index: &str, len: usize) -> Result<usize, String> {
+    match index.parse::<usize>() {
+        Ok(i) if i < len => Ok(i),
+        _ => Err(format!("no object with index {}", index)),
+    }
+}
+
+struct Tokens<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn is_empty(&self) -> bool {
+        self.next >= self.tokens.len()
+    }
+
+    fn word(&mut self) -> Result<&'a str, String> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .ok_or_else(|| String::from("missing value"))?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        let token = self.word()?;
+        token
+            .parse()
+            .map_err(|_| format!("invalid number {}", token))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3f32, String> {
+        Ok(Vec3f32::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn rgb(&mut self) -> Result<Rgb, String> {
+        Ok(Rgb::new(self.number()?, self.number()?, self.number()?))
+    }
+}
@@ -0,0 +1,612 @@
+//! Local HTTP API queueing render jobs, for tools which submit scenes rather than run the
+//! binary. Jobs render one at a time in the order they were submitted.
+//!
+//! ```text
+//! POST   /jobs?spp=64&integrator=bdpt   scene document in the body, the default scene if empty
+//! GET    /jobs                          every job
+//! GET    /jobs/<id>                     status and progress of a job
+//! DELETE /jobs/<id>                     cancels a queued or running job, forgets a finished one
+//! GET    /jobs/<id>/image[?format=]     image of a finished job, png, ppm or pfm
+//! GET    /jobs/<id>/stats               statistics report of a finished job, in JSON
+//! ```
+//!
+//! The query holds render options of the command line without their dashes, `denoise` alone
+//! for a flag, see `JOB_OPTIONS`. Options reading or writing files are refused, and so are
+//! images, sample counts and photon maps above the `MAX_JOB_*` limits. Paths in the
+//! documents are relative to the assets directory of the server and cannot leave it.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use image::png::PNGEncoder;
+use image::ColorType;
+
+use crate::color::Rgb;
+use crate::float_image::encode_pfm;
+use crate::progress::Progress;
+use crate::scene_file::parse_scene;
+use crate::stats::{self, Report};
+use crate::{
+    apply_options, load, parse_args, pass_count, render_image, round_count, setup_frame, to_rgb8,
+    RayTracerError, RenderOptions, Rendered, ResultRayTracer,
+};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+/// Largest scene document accepted.
+const MAX_BODY: usize = 64 << 20;
+const MAX_HEADERS: usize = 100;
+/// Longest request line or header line accepted.
+const MAX_LINE: usize = 8 << 10;
+/// Time a client may stay silent before its connection is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest image of a job, a 4K UHD frame.
+const MAX_JOB_PIXELS: usize = 3840 * 2160;
+const MAX_JOB_SAMPLES: u32 = 1 << 16;
+/// Most photons of each photon map of a job.
+const MAX_JOB_PHOTONS: usize = 10_000_000;
+
+/// Options of the command line a job may set, the ones of the rendering of a single image.
+const JOB_OPTIONS: &[&str] = &[
+    "spp",
+    "min-spp",
+    "threshold",
+    "denoise",
+    "denoise-iterations",
+    "integrator",
+    "ao-samples",
+    "ao-distance",
+    "ao-shading",
+    "env-samples",
+    "spectral",
+    "max-depth",
+    "photons",
+    "caustic-photons",
+    "photon-radius",
+    "final-gather",
+    "progressive",
+    "sky",
+    "turbidity",
+    "ground-albedo",
+    "sun",
+    "sun-location",
+    "date",
+    "time",
+    "utc-offset",
+    "tile-size",
+    "tile-order",
+    "width",
+    "height",
+    "fov",
+    "projection",
+    "ortho-height",
+    "aperture",
+    "f-stop",
+    "focus-distance",
+    "autofocus",
+    "shutter",
+    "camera-velocity",
+    "blades",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Status {
+    Queued,
+    Rendering,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Rendering => "rendering",
+            Status::Done => "done",
+            Status::Failed(_) => "failed",
+            Status::Cancelled => "cancelled",
+        }
+    }
+}
+
+struct Job {
+    id: u64,
+    status: Status,
+    options: RenderOptions,
+    /// Scene document, given to the renderer when the job starts.
+    document: String,
+    progress: Option<Arc<Progress>>,
+    cancel: Arc<AtomicBool>,
+    image: Option<Vec<Rgb>>,
+    report: Option<Report>,
+}
+
+impl Job {
+    fn to_json(&self) -> String {
+        let progress = match (&self.status, &self.progress) {
+            (Status::Done, _) => 1.,
+            (_, Some(progress)) => progress.fraction(),
+            _ => 0.,
+        };
+        let error = match self.status {
+            Status::Failed(ref msg) => format!(", \"error\": {}", json_string(msg)),
+            _ => String::new(),
+        };
+        format!(
+            "{{\"id\": {}, \"status\": \"{}\", \"progress\": {:.4}, \"width\": {}, \"height\": {}{}}}",
+            self.id,
+            self.status.name(),
+            progress,
+            self.options.width,
+            self.options.height,
+            error
+        )
+    }
+}
+
+struct Jobs {
+    list: Vec<Job>,
+    next_id: u64,
+}
+
+struct Server {
+    jobs: Mutex<Jobs>,
+    /// Signaled when a job is queued.
+    queued: Condvar,
+    assets: PathBuf,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+fn json(status: u16, body: String) -> Response {
+    Response {
+        status,
+        content_type: "application/json",
+        body: body.into_bytes(),
+    }
+}
+
+fn error(status: u16, msg: &str) -> Response {
+    json(status, format!("{{\"error\": {}}}", json_string(msg)))
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Decodes the `%XX` escapes and the `+` spaces of a query component.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Reads a line into `line`, returns false when it is longer than `MAX_LINE`.
+fn read_line(reader: &mut BufReader<TcpStream>, line: &mut String) -> io::Result<bool> {
+    line.clear();
+    reader.by_ref().take(MAX_LINE as u64).read_line(line)?;
+    Ok(line.len() < MAX_LINE || line.ends_with('\n'))
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Result<Request, Response>> {
+    let mut line = String::new();
+    if !read_line(reader, &mut line)? {
+        return Ok(Err(error(414, "request line too long")));
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(Err(error(400, "malformed request line"))),
+    };
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        if !read_line(reader, &mut line)? {
+            return Ok(Err(error(431, "header line too long")));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Err(error(431, "too many headers")));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(length) => content_length = length,
+                    Err(_) => return Ok(Err(error(400, "bad Content-Length"))),
+                }
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Ok(Err(error(413, "scene document too large")));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+    Ok(Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body,
+    }))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn handle_connection(server: &Server, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader)? {
+        Ok(request) => handle(server, &request),
+        Err(response) => response,
+    };
+    write_response(&mut reader.into_inner(), &response)
+}
+
+fn handle(server: &Server, request: &Request) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let id = segments.get(1).and_then(|id| id.parse::<u64>().ok());
+    match (request.method.as_str(), segments.as_slice(), id) {
+        ("POST", ["jobs"], _) => submit(server, request),
+        ("GET", ["jobs"], _) => {
+            let jobs = server.jobs.lock().unwrap();
+            let list: Vec<String> = jobs.list.iter().map(Job::to_json).collect();
+            json(200, format!("[{}]", list.join(", ")))
+        }
+        ("GET", ["jobs", _], Some(id)) => with_job(server, id, |job| json(200, job.to_json())),
+        ("DELETE", ["jobs", _], Some(id)) => cancel(server, id),
+        ("GET", ["jobs", _, "image"], Some(id)) => {
+            let format = request
+                .query
+                .iter()
+                .find(|(key, _)| key == "format")
+                .map_or("png", |(_, value)| value.as_str());
+            with_job(server, id, |job| image(job, format))
+        }
+        ("GET", ["jobs", _, "stats"], Some(id)) => with_job(server, id, |job| match job.report {
+            Some(ref report) => json(200, report.to_json()),
+            None => error(409, &format!("job is {}", job.status.name())),
+        }),
+        (_, ["jobs"], _) | (_, ["jobs", _], Some(_)) | (_, ["jobs", _, _], Some(_)) => {
+            error(405, "method not allowed")
+        }
+        _ => error(404, "not found"),
+    }
+}
+
+fn with_job(server: &Server, id: u64, f: impl FnOnce(&Job) -> Response) -> Response {
+    let jobs = server.jobs.lock().unwrap();
+    match jobs.list.iter().find(|job| job.id == id) {
+        Some(job) => f(job),
+        None => error(404, "no such job"),
+    }
+}
+
+fn submit(server: &Server, request: &Request) -> Response {
+    if let Some((key, _)) = request
+        .query
+        .iter()
+        .find(|(key, _)| !JOB_OPTIONS.contains(&key.as_str()))
+    {
+        return error(400, &format!("option {} cannot be set by jobs", key));
+    }
+    let args: Vec<String> = request
+        .query
+        .iter()
+        .flat_map(|(key, value)| {
+            Some(format!("--{}", key))
+                .into_iter()
+                .chain(Some(value.clone()).filter(|value| !value.is_empty()))
+        })
+        .collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => return error(400, &err.to_string()),
+    };
+    if let Some(message) = oversized(&options) {
+        return error(400, message);
+    }
+    let document = match String::from_utf8(request.body.clone()) {
+        Ok(document) => document,
+        Err(_) => return error(400, "scene document is not UTF-8"),
+    };
+
+    let mut jobs = server.jobs.lock().unwrap();
+    let id = jobs.next_id;
+    jobs.next_id += 1;
+    let job = Job {
+        id,
+        status: Status::Queued,
+        options,
+        document,
+        progress: None,
+        cancel: Arc::new(AtomicBool::new(false)),
+        image: None,
+        report: None,
+    };
+    let response = json(201, job.to_json());
+    jobs.list.push(job);
+    server.queued.notify_one();
+    response
+}
+
+/// Why the job of `options` needs more memory or time than a job may take, if it does.
+fn oversized(options: &RenderOptions) -> Option<&'static str> {
+    let photons = &options.photons;
+    if options.width.saturating_mul(options.height) > MAX_JOB_PIXELS {
+        Some("image too large")
+    } else if options.max_samples > MAX_JOB_SAMPLES {
+        Some("too many samples per pixel")
+    } else if photons.global_photons.max(photons.caustic_photons) > MAX_JOB_PHOTONS {
+        Some("too many photons")
+    } else {
+        None
+    }
+}
+
+fn cancel(server: &Server, id: u64) -> Response {
+    let mut jobs = server.jobs.lock().unwrap();
+    let index = match jobs.list.iter().position(|job| job.id == id) {
+        Some(index) => index,
+        None => return error(404, "no such job"),
+    };
+    let job = &mut jobs.list[index];
+    match job.status {
+        Status::Queued => job.status = Status::Cancelled,
+        // The renderer marks the job cancelled once the tiles in progress are done.
+        Status::Rendering => job.cancel.store(true, Ordering::Relaxed),
+        _ => {
+            let job = jobs.list.remove(index);
+            return json(200, job.to_json());
+        }
+    }
+    json(200, job.to_json())
+}
+
+fn image(job: &Job, format: &str) -> Response {
+    let pixels = match job.image {
+        Some(ref pixels) => pixels,
+        None => return error(409, &format!("job is {}", job.status.name())),
+    };
+    let (width, height) = (job.options.width, job.options.height);
+    match format {
+        "png" => {
+            let mut bytes = Vec::new();
+            let encoded = PNGEncoder::new(&mut bytes).encode(
+                &to_rgb8(pixels),
+                width as u32,
+                height as u32,
+                ColorType::RGB(8),
+            );
+            match encoded {
+                Ok(()) => Response {
+                    status: 200,
+                    content_type: "image/png",
+                    body: bytes,
+                },
+                Err(err) => error(500, &err.to_string()),
+            }
+        }
+        "ppm" => {
+            let mut body = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+            body.extend(to_rgb8(pixels));
+            Response {
+                status: 200,
+                content_type: "image/x-portable-pixmap",
+                body,
+            }
+        }
+        "pfm" => Response {
+            status: 200,
+            content_type: "application/octet-stream",
+            body: encode_pfm(width, height, pixels),
+        },
+        _ => error(400, &format!("unknown image format {}", format)),
+    }
+}
+
+/// Renders the scene `document` with `options`, or the default scene when it is empty.
+fn run_job(
+    options: &mut RenderOptions,
+    document: &str,
+    assets: &Path,
+    progress: &Progress,
+) -> Result<(Rendered, Report), RayTracerError> {
+    let before = stats::snapshot();
+    let (mut scene, animation) = if document.trim().is_empty() {
+        load(options)?
+    } else {
+        let file = stats::time_phase("scene", || {
+            parse_scene(
+                document,
+                Path::new("document"),
+                assets,
+                true,
+                &mut options.camera,
+            )
+        })?;
+        let mut scene = file.scene;
+        apply_options(options, &mut scene)?;
+        (scene, file.animation)
+    };
+    let options = setup_frame(options, 0, &mut scene, &animation);
+    let rendered = render_image(&scene, &options, &options.camera, None, &|tile, _| {
+        progress.advance(tile.width * tile.height)
+    })?;
+    Ok((rendered, Report::since(&before)))
+}
+
+/// Renders the queued jobs one after another, forever.
+fn render_jobs(server: &Server) {
+    loop {
+        let (id, mut options, document, progress) = {
+            let mut jobs = server.jobs.lock().unwrap();
+            let index = loop {
+                match jobs
+                    .list
+                    .iter()
+                    .position(|job| job.status == Status::Queued)
+                {
+                    Some(index) => break index,
+                    None => jobs = server.queued.wait(jobs).unwrap(),
+                }
+            };
+            let job = &mut jobs.list[index];
+            let pixels = job.options.width
+                * job.options.height
+                * (pass_count(&job.options) * round_count(&job.options)) as usize;
+            let progress = Arc::new(Progress::new("", pixels, false));
+            job.status = Status::Rendering;
+            job.progress = Some(Arc::clone(&progress));
+            job.options.cancel = Some(Arc::clone(&job.cancel));
+            let document = std::mem::take(&mut job.document);
+            (job.id, job.options.clone(), document, progress)
+        };
+
+        let result = run_job(&mut options, &document, &server.assets, &progress);
+        let mut jobs = server.jobs.lock().unwrap();
+        // A finished job may have been forgotten in the meantime.
+        let job = match jobs.list.iter_mut().find(|job| job.id == id) {
+            Some(job) => job,
+            None => continue,
+        };
+        job.status = match result {
+            Ok(((framebuffer, _, _), report)) => {
+                job.image = Some(framebuffer);
+                job.report = Some(report);
+                Status::Done
+            }
+            Err(RayTracerError::Cancelled) => Status::Cancelled,
+            Err(err) => Status::Failed(err.to_string()),
+        };
+        println!("Job {} {}", id, job.status.name());
+    }
+}
+
+/// The `serve` subcommand: listens for HTTP requests and renders the jobs they submit.
+pub fn serve_command(args: &[String]) -> ResultRayTracer {
+    let mut address = String::from(DEFAULT_ADDRESS);
+    let mut assets = PathBuf::from(".");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| RayTracerError::Args(format!("missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--address" => address = value()?.clone(),
+            "--assets" => assets = PathBuf::from(value()?),
+            _ => return Err(RayTracerError::Args(format!("unknown argument {}", arg))),
+        }
+    }
+
+    let listener = TcpListener::bind(&address)?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    let server = Arc::new(Server {
+        jobs: Mutex::new(Jobs {
+            list: Vec::new(),
+            next_id: 1,
+        }),
+        queued: Condvar::new(),
+        assets,
+    });
+    let renderer = Arc::clone(&server);
+    thread::spawn(move || render_jobs(&renderer));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Connection failed: {}", err);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(err) = handle_connection(&server, stream) {
+                println!("Request failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,383 @@
+//! Analytic daylight: the Preetham sky model baked into an env map and the sun as a disk light
+//! of its true angular size.
+//! https://courses.cs.duke.edu/fall01/cps124/resources/p91-preetham.pdf
+//!
+//! The scene is oriented with north toward -z, east toward +x and up toward +y, the default
+//! camera looks north. Radiances are in units of `LUMINANCE_UNIT`.
+
+use std::f32::consts::PI;
+
+use crate::bsdf::sample_uniform_cone;
+use crate::color::Rgb;
+use crate::medium::shadow_transmittance;
+use crate::sampler::Rng;
+use crate::scene::{scene_intersect, EnvMap, Material, Scene};
+use crate::spectrum::xyz_to_srgb;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+
+/// Luminance in cd/m² of a radiance of 1, puts a clear sky around the range of an LDR env map.
+const LUMINANCE_UNIT: f32 = 10_000.;
+
+/// Half of the 0.53 degrees the sun spans seen from the earth.
+const SUN_ANGULAR_RADIUS: f32 = 0.004_625;
+
+/// Luminance of the sun above the atmosphere, in cd/m².
+const SUN_LUMINANCE: f32 = 1.88e9;
+
+/// Resolution of the baked env map.
+const SKY_WIDTH: usize = 1024;
+const SKY_HEIGHT: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunPosition {
+    /// Degrees, the azimuth goes clockwise from north.
+    Angles { azimuth: f32, elevation: f32 },
+    /// Degrees with east longitudes positive, local date and time with the offset of the time
+    /// zone from UTC in hours.
+    Location {
+        latitude: f32,
+        longitude: f32,
+        date: (i32, u32, u32),
+        hours: f32,
+        utc_offset: f32,
+    },
+}
+
+impl SunPosition {
+    /// Azimuth and elevation in degrees.
+    pub fn angles(&self) -> (f32, f32) {
+        match *self {
+            SunPosition::Angles { azimuth, elevation } => (azimuth, elevation),
+            SunPosition::Location {
+                latitude,
+                longitude,
+                date,
+                hours,
+                utc_offset,
+            } => solar_angles(latitude, longitude, date, hours, utc_offset),
+        }
+    }
+
+    /// Unit vector toward the sun.
+    pub fn direction(&self) -> Vec3f32 {
+        let (azimuth, elevation) = self.angles();
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        Vec3f32::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        )
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date.
+pub fn parse_date(date: &str) -> Result<(i32, u32, u32), String> {
+    let error = || format!("invalid date {}, expected YYYY-MM-DD", date);
+    match date.split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] => {
+            let month: u32 = month.parse().map_err(|_| error())?;
+            let day: u32 = day.parse().map_err(|_| error())?;
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return Err(error());
+            }
+            Ok((year.parse().map_err(|_| error())?, month, day))
+        }
+        _ => Err(error()),
+    }
+}
+
+/// Parses a `HH:MM` time of day into hours.
+pub fn parse_time(time: &str) -> Result<f32, String> {
+    let error = || format!("invalid time {}, expected HH:MM", time);
+    match time.split(':').collect::<Vec<_>>()[..] {
+        [hours, minutes] => {
+            let hours: f32 = hours.parse().map_err(|_| error())?;
+            let minutes: f32 = minutes.parse().map_err(|_| error())?;
+            Ok(hours + minutes / 60.)
+        }
+        _ => Err(error()),
+    }
+}
+
+fn day_of_year((year, month, day): (i32, u32, u32)) -> u32 {
+    const DAYS_BEFORE: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month = month.clamp(1, 12);
+    DAYS_BEFORE[month as usize - 1] + day + u32::from(leap && month > 2)
+}
+
+// https://gml.noaa.gov/grad/solcalc/solareqns.PDF
+/// Azimuth and elevation of the sun in degrees, from the NOAA approximations of the equation
+/// of time and of the declination.
+fn solar_angles(
+    latitude: f32,
+    longitude: f32,
+    date: (i32, u32, u32),
+    hours: f32,
+    utc_offset: f32,
+) -> (f32, f32) {
+    let gamma = 2. * PI / 365. * (day_of_year(date) as f32 - 1. + (hours - utc_offset - 12.) / 24.);
+    let equation_of_time = 229.18
+        * (0.000_075 + 0.001_868 * gamma.cos()
+            - 0.032_077 * gamma.sin()
+            - 0.014_615 * (2. * gamma).cos()
+            - 0.040_849 * (2. * gamma).sin());
+    let declination = 0.006_918 - 0.399_912 * gamma.cos() + 0.070_257 * gamma.sin()
+        - 0.006_758 * (2. * gamma).cos()
+        + 0.000_907 * (2. * gamma).sin()
+        - 0.002_697 * (3. * gamma).cos()
+        + 0.001_48 * (3. * gamma).sin();
+    let solar_minutes = hours * 60. + equation_of_time + 4. * longitude - 60. * utc_offset;
+    let hour_angle = (solar_minutes / 4. - 180.).to_radians();
+    let latitude = latitude.to_radians();
+
+    let cos_zenith =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = 90. - cos_zenith.clamp(-1., 1.).acos().to_degrees();
+    // Measured from south toward west, turned to clockwise from north.
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos());
+    ((azimuth.to_degrees() + 180.).rem_euclid(360.), elevation)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+    /// Haze of the atmosphere, 2 is a clear day and 10 a hazy one.
+    pub turbidity: f32,
+    /// Reflectance of the ground filling the lower half of the env map.
+    pub ground_albedo: Rgb,
+    pub sun: SunPosition,
+}
+
+impl Default for SkySettings {
+    fn default() -> SkySettings {
+        SkySettings {
+            turbidity: 3.,
+            ground_albedo: Rgb::new(0.3, 0.3, 0.3),
+            sun: SunPosition::Angles {
+                azimuth: 160.,
+                elevation: 35.,
+            },
+        }
+    }
+}
+
+/// The sun disk, a distant light seen under its true angular size.
+#[derive(Debug)]
+pub struct Sun {
+    pub direction: Vec3f32,
+    cos_radius: f32,
+    pub radiance: Rgb,
+}
+
+impl Sun {
+    /// Radiance of the disk seen in direction `dir`.
+    pub fn disk(&self, dir: &Vec3f32) -> Rgb {
+        let mut dir = *dir;
+        dir.normalize();
+        if dir.dot_product(&self.direction) >= self.cos_radius {
+            self.radiance
+        } else {
+            Rgb::new(0., 0., 0.)
+        }
+    }
+
+    fn solid_angle(&self) -> f32 {
+        2. * PI * (1. - self.cos_radius)
+    }
+
+    /// Direction toward a point of the disk seen from `p` and the light arriving from it in the
+    /// units of the point light intensities of Whitted shading: the sun irradiance at normal
+    /// incidence divided by pi, attenuated by the occluders and the media on the way.
+    pub fn lighting(
+        &self,
+        scene: &Scene,
+        p: &Vec3f32,
+        n: &Vec3f32,
+        medium: Option<usize>,
+        time: f32,
+        rng: &mut Rng,
+    ) -> Option<(Vec3f32, Rgb)> {
+        let dir = sample_uniform_cone(
+            &self.direction,
+            self.cos_radius,
+            rng.next_f32(),
+            rng.next_f32(),
+        );
+        let orig = if dir.dot_product(n) < 0. {
+            p - n * 1e-3
+        } else {
+            p + n * 1e-3
+        };
+        let transmittance = if scene.media.is_empty() {
+            let mut hit = Vec3f32::new(0., 0., 0.);
+            let mut hit_n = Vec3f32::new(0., 0., 0.);
+            let mut material = Material::default();
+            stats::count(Counter::ShadowRays, 1);
+            if scene_intersect(
+                &orig,
+                &dir,
+                time,
+                scene,
+                &mut hit,
+                &mut hit_n,
+                &mut material,
+            ) {
+                return None;
+            }
+            Rgb::new(1., 1., 1.)
+        } else {
+            shadow_transmittance(scene, &orig, &dir, scene.fog_distance, medium, time, rng)
+        };
+        Some((
+            dir,
+            transmittance * self.radiance * (self.solid_angle() / PI),
+        ))
+    }
+}
+
+/// Perez distribution coefficients A to E of the luminance Y and chromaticities x and y.
+fn perez_coefficients(turbidity: f32) -> [[f32; 5]; 3] {
+    let t = turbidity;
+    [
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+    ]
+}
+
+/// `theta` is the zenith angle of the viewing direction and `gamma` its angle to the sun.
+fn perez(c: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let cos_theta = theta.cos().max(0.01);
+    (1. + c[0] * (c[1] / cos_theta).exp())
+        * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+/// Luminance in kcd/m² and chromaticities of the zenith for the sun zenith angle `theta_sun`.
+fn zenith(turbidity: f32, theta_sun: f32) -> [f32; 3] {
+    let t = turbidity;
+    let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+    let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let chromaticity = |m: [[f32; 4]; 3]| {
+        let s = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.];
+        let row = |r: [f32; 4]| r.iter().zip(&s).map(|(a, b)| a * b).sum::<f32>();
+        t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+    };
+    let x = chromaticity([
+        [0.00166, -0.00375, 0.00209, 0.],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let y = chromaticity([
+        [0.00275, -0.00610, 0.00317, 0.],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+    [luminance, x, y]
+}
+
+/// Sun radiance after the Rayleigh and aerosol extinction along the air mass of its elevation,
+/// with the optical depths of Preetham's appendix and no ozone.
+fn sun_radiance(turbidity: f32, theta_sun: f32) -> Rgb {
+    let cos = theta_sun.cos();
+    if cos <= 0. {
+        return Rgb::new(0., 0., 0.);
+    }
+    // Kasten and Young air mass.
+    let air_mass = 1. / (cos + 0.505_72 * (96.079_95 - theta_sun.to_degrees()).powf(-1.6364));
+    let beta = 0.046_08 * turbidity - 0.045_86;
+    let transmittance = |lambda_um: f32| {
+        let rayleigh = 0.008_735 * lambda_um.powf(-4.08);
+        let aerosol = beta * lambda_um.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Rgb::new(
+        transmittance(0.61),
+        transmittance(0.55),
+        transmittance(0.465),
+    ) * (SUN_LUMINANCE / LUMINANCE_UNIT)
+}
+
+/// Env map of the sky without the sun disk, the lower half is the ground lit by the sky and the
+/// sun, and the matching sun light, `None` below the horizon.
+pub fn bake(settings: &SkySettings) -> (EnvMap, Option<Sun>) {
+    let sun_dir = settings.sun.direction();
+    // The model does not hold at night, the sky stays at its sunset look.
+    let theta_sun = sun_dir.y.clamp(-1., 1.).acos().min(PI / 2.);
+    let coefficients = perez_coefficients(settings.turbidity);
+    let zenith = zenith(settings.turbidity, theta_sun);
+
+    let sky = |dir: &Vec3f32| {
+        let theta = dir.y.clamp(-1., 1.).acos();
+        let gamma = dir.dot_product(&sun_dir).clamp(-1., 1.).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let c = &coefficients[i];
+            zenith[i] * perez(c, theta, gamma) / perez(c, 0., theta_sun)
+        });
+        let luminance = luminance * 1000. / LUMINANCE_UNIT;
+        let rgb = xyz_to_srgb([x * luminance / y, luminance, (1. - x - y) * luminance / y]);
+        Rgb::new(rgb.r.max(0.), rgb.g.max(0.), rgb.b.max(0.))
+    };
+
+    let mut pixels = vec![Rgb::new(0., 0., 0.); SKY_WIDTH * SKY_HEIGHT];
+    // Irradiance of a horizontal surface from the sky, summed over the upper half.
+    let mut sky_irradiance = Rgb::new(0., 0., 0.);
+    let texel_area = 2. * PI * PI / (SKY_WIDTH * SKY_HEIGHT) as f32;
+    for y in 0..SKY_HEIGHT / 2 {
+        for x in 0..SKY_WIDTH {
+            let u = (x as f32 + 0.5) / SKY_WIDTH as f32;
+            let v = (y as f32 + 0.5) / SKY_HEIGHT as f32;
+            let dir = EnvMap::direction(u, v);
+            let radiance = sky(&dir);
+            let sin_theta = (v * PI).sin();
+            sky_irradiance = sky_irradiance + radiance * (dir.y * sin_theta * texel_area);
+            pixels[y * SKY_WIDTH + x] = radiance;
+        }
+    }
+
+    let sun = if sun_dir.y > 0. {
+        let radiance = sun_radiance(settings.turbidity, theta_sun);
+        Some(Sun {
+            direction: sun_dir,
+            cos_radius: SUN_ANGULAR_RADIUS.cos(),
+            radiance,
+        })
+    } else {
+        None
+    };
+    let sun_irradiance = match sun {
+        Some(ref sun) => sun.radiance * (sun.solid_angle() * sun_dir.y),
+        None => Rgb::new(0., 0., 0.),
+    };
+    let ground = settings.ground_albedo * (sky_irradiance + sun_irradiance) / PI;
+    for pixel in &mut pixels[SKY_HEIGHT / 2 * SKY_WIDTH..] {
+        *pixel = ground;
+    }
+
+    let map = EnvMap {
+        width: SKY_WIDTH,
+        height: SKY_HEIGHT,
+        pixels,
+    };
+    (map, sun)
+}
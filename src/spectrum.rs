@@ -0,0 +1,373 @@
+//! Spectral rendering with hero wavelength sampling (Wilkie et al. 2014): every camera sample
+//! carries a few wavelengths spread evenly over the visible range from one random hero
+//! wavelength. RGB colors are upsampled to spectra with Smits' method and the result goes back
+//! to linear sRGB through the CIE XYZ color matching functions.
+
+use std::ops::{Add, Mul};
+use std::sync::OnceLock;
+
+use crate::ambient_occlusion::ambient_occlusion;
+use crate::color::Rgb;
+use crate::emitter::sample_emitters;
+use crate::sampler::Rng;
+use crate::scene::{scene_intersect, Material, Scene};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3f32;
+use crate::{reflect, refract};
+
+pub const LAMBDA_MIN: f32 = 380.;
+pub const LAMBDA_MAX: f32 = 720.;
+/// Wavelengths traced together.
+pub const WAVELENGTHS: usize = 4;
+
+/// Wavelength dependent index of refraction, wavelengths in micrometers as usual for the
+/// published coefficients.
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    /// n = a + b / λ²
+    Cauchy { a: f32, b: f32 },
+    /// n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn refractive_index(&self, lambda_nm: f32) -> f32 {
+        let l2 = (lambda_nm * 1e-3) * (lambda_nm * 1e-3);
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Spectrum(pub [f32; WAVELENGTHS]);
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+    fn add(self, other: Spectrum) -> Spectrum {
+        let mut s = self.0;
+        for (a, b) in s.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+        Spectrum(s)
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+    fn mul(self, other: Spectrum) -> Spectrum {
+        let mut s = self.0;
+        for (a, b) in s.iter_mut().zip(other.0.iter()) {
+            *a *= b;
+        }
+        Spectrum(s)
+    }
+}
+
+impl Mul<f32> for Spectrum {
+    type Output = Spectrum;
+    fn mul(self, other: f32) -> Spectrum {
+        Spectrum(self.0.map(|a| a * other))
+    }
+}
+
+impl Spectrum {
+    pub fn constant(value: f32) -> Spectrum {
+        Spectrum([value; WAVELENGTHS])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    /// The first one is the hero wavelength, in nanometers.
+    pub lambda: [f32; WAVELENGTHS],
+    pdf: [f32; WAVELENGTHS],
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.; WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / WAVELENGTHS as f32).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+        Wavelengths {
+            lambda,
+            pdf: [1. / range; WAVELENGTHS],
+        }
+    }
+
+    /// Keeps only the hero wavelength, once the path depends on the wavelength.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0. {
+            return;
+        }
+        self.pdf[0] /= WAVELENGTHS as f32;
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.;
+        }
+    }
+}
+
+// Smits, "An RGB to Spectrum Conversion for Reflectances", 10 bins over [380, 720] nm.
+const SMITS_WHITE: [f32; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Value at `lambda` of the Smits spectrum of `rgb`.
+fn smits(rgb: &Rgb, lambda: f32) -> f32 {
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.) as usize).min(9);
+    let (r, g, b) = (rgb.r, rgb.g, rgb.b);
+    // The smallest component gives white, the middle one the secondary color holding both.
+    if r <= g && r <= b {
+        let mut v = r * SMITS_WHITE[bin];
+        if g <= b {
+            v += (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin];
+        } else {
+            v += (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin];
+        }
+        v
+    } else if g <= r && g <= b {
+        let mut v = g * SMITS_WHITE[bin];
+        if r <= b {
+            v += (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin];
+        } else {
+            v += (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin];
+        }
+        v
+    } else {
+        let mut v = b * SMITS_WHITE[bin];
+        if r <= g {
+            v += (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin];
+        } else {
+            v += (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin];
+        }
+        v
+    }
+}
+
+pub fn from_rgb(rgb: &Rgb, wavelengths: &Wavelengths) -> Spectrum {
+    Spectrum(wavelengths.lambda.map(|l| smits(rgb, l).max(0.)))
+}
+
+// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+// Functions", multi-lobe fit.
+fn lobe(lambda: f32, mu: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+fn cie_xyz(lambda: f32) -> [f32; 3] {
+    [
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    ]
+}
+
+pub fn xyz_to_srgb(xyz: [f32; 3]) -> Rgb {
+    let [x, y, z] = xyz;
+    Rgb::new(
+        3.240_454 * x - 1.537_138 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    )
+}
+
+/// Linear sRGB of the constant spectrum 1, divided out so white stays white.
+fn white_balance() -> Rgb {
+    static WHITE: OnceLock<Rgb> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let mut xyz = [0.; 3];
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        for i in 0..steps {
+            let cmf = cie_xyz(LAMBDA_MIN + i as f32 + 0.5);
+            for c in 0..3 {
+                xyz[c] += cmf[c];
+            }
+        }
+        xyz_to_srgb(xyz)
+    })
+}
+
+/// Monte Carlo estimate of the color of `spectrum`.
+pub fn to_rgb(spectrum: &Spectrum, wavelengths: &Wavelengths) -> Rgb {
+    let mut xyz = [0.; 3];
+    for i in 0..WAVELENGTHS {
+        if wavelengths.pdf[i] == 0. {
+            continue;
+        }
+        let cmf = cie_xyz(wavelengths.lambda[i]);
+        let weight = spectrum.0[i] / (wavelengths.pdf[i] * WAVELENGTHS as f32);
+        for c in 0..3 {
+            xyz[c] += cmf[c] * weight;
+        }
+    }
+    let rgb = xyz_to_srgb(xyz);
+    let white = white_balance();
+    Rgb::new(rgb.r / white.r, rgb.g / white.g, rgb.b / white.b)
+}
+
+/// Whitted shading as in `cast_ray` for the sampled wavelengths, refraction through a
+/// dispersive material only keeps the hero wavelength. Scenes with participating media and
+/// the env map light are rejected before rendering in spectral mode.
+fn cast_ray(
+    orig: &Vec3f32,
+    dir: &Vec3f32,
+    time: f32,
+    scene: &Scene,
+    wavelengths: &mut Wavelengths,
+    rng: &mut Rng,
+    depth: usize,
+) -> Spectrum {
+    let mut point = Vec3f32::new(0., 0., 0.);
+    let mut n = Vec3f32::new(0., 0., 0.);
+    let mut material = Material::default();
+
+    if !scene_intersect(orig, dir, time, scene, &mut point, &mut n, &mut material) || depth > 4 {
+        return from_rgb(&scene.environment(dir), wavelengths);
+    }
+    stats::record_depth(depth + 1);
+
+    let offset = |d: &Vec3f32| {
+        if d.dot_product(&n) < 0. {
+            point - &n * 1e-3
+        } else {
+            point + &n * 1e-3
+        }
+    };
+    let mut reflect_dir = reflect(dir, &n);
+    reflect_dir.normalize();
+    let reflect_color = cast_ray(
+        &offset(&reflect_dir),
+        &reflect_dir,
+        time,
+        scene,
+        wavelengths,
+        rng,
+        depth + 1,
+    );
+
+    let refract_color = if material.albedo.a != 0. {
+        let ior = material.refractive_index_at(wavelengths.lambda[0]);
+        if material.dispersion.is_some() {
+            wavelengths.terminate_secondary();
+        }
+        let mut refract_dir = refract(dir, &n, ior, 1.);
+        refract_dir.normalize();
+        cast_ray(
+            &offset(&refract_dir),
+            &refract_dir,
+            time,
+            scene,
+            wavelengths,
+            rng,
+            depth + 1,
+        )
+    } else {
+        Spectrum::constant(0.)
+    };
+
+    let mut diffuse_light_intensity = 0.;
+    let mut specular_light_intensity = 0.;
+    for l in &scene.lights {
+        let mut light_dir = l.position - point;
+        let light_distance = light_dir.norm();
+        light_dir.normalize();
+
+        let shadow_orig = point + &n * 1e-3;
+        let mut shadow_pt = Vec3f32::new(0., 0., 0.);
+        let mut shadow_n = Vec3f32::new(0., 0., 0.);
+        let mut tmp_material = Material::default();
+        stats::count(Counter::ShadowRays, 1);
+        if scene_intersect(
+            &shadow_orig,
+            &light_dir,
+            time,
+            scene,
+            &mut shadow_pt,
+            &mut shadow_n,
+            &mut tmp_material,
+        ) && (shadow_pt - shadow_orig).norm() < light_distance
+        {
+            continue;
+        }
+
+        diffuse_light_intensity += l.intensity * light_dir.dot_product(&n).max(0.);
+        let vec_reflect = reflect(&(light_dir * -1.), &n) * -1.;
+        specular_light_intensity += vec_reflect
+            .dot_product(dir)
+            .max(0.)
+            .powf(material.specular_exponent)
+            * l.intensity
+    }
+
+    // Sun and emissive spheres.
+    let mut sampled_diffuse = Spectrum::constant(0.);
+    let mut sampled_specular = Spectrum::constant(0.);
+    let mut add_light = |light_dir: Vec3f32, intensity: Rgb| {
+        let intensity = from_rgb(&intensity, wavelengths);
+        sampled_diffuse = sampled_diffuse + intensity * light_dir.dot_product(&n).max(0.);
+        let vec_reflect = reflect(&(light_dir * -1.), &n) * -1.;
+        sampled_specular = sampled_specular
+            + intensity
+                * vec_reflect
+                    .dot_product(dir)
+                    .max(0.)
+                    .powf(material.specular_exponent);
+    };
+    if let Some(ref sun) = scene.sun {
+        if let Some((sun_dir, intensity)) = sun.lighting(scene, &point, &n, None, time, rng) {
+            add_light(sun_dir, intensity);
+        }
+    }
+    sample_emitters(scene, &point, &n, None, time, rng, add_light);
+
+    let ao = match scene.ambient_occlusion {
+        Some(ref settings) if material.albedo.r != 0. => {
+            ambient_occlusion(scene, settings, &point, &n, time, rng)
+        }
+        _ => 1.,
+    };
+    from_rgb(&material.diffuse_color, wavelengths)
+        * (Spectrum::constant(diffuse_light_intensity) + sampled_diffuse)
+        * (material.albedo.r * ao)
+        + (Spectrum::constant(specular_light_intensity) + sampled_specular) * material.albedo.g
+        + reflect_color * material.albedo.b
+        + refract_color * material.albedo.a
+        + from_rgb(&material.emitted(scene, &n, dir), wavelengths)
+}
+
+/// Color of one camera sample traced with wavelengths drawn from `rng`.
+pub fn trace(orig: &Vec3f32, dir: &Vec3f32, time: f32, scene: &Scene, rng: &mut Rng) -> Rgb {
+    let mut wavelengths = Wavelengths::sample(rng.next_f32());
+    let spectrum = cast_ray(orig, dir, time, scene, &mut wavelengths, rng, 0);
+    to_rgb(&spectrum, &wavelengths)
+}
@@ -0,0 +1,243 @@
+//! Counters of the work done by the renderer and time spent in each phase, summed into the
+//! statistics report printed after a render.
+//!
+//! Every thread counts into its own slots, which only it writes, so that counting in the
+//! traversal loops stays as cheap as a plain addition.
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Counter {
+    /// Rays leaving the camera.
+    PrimaryRays,
+    /// Visibility tests toward lights.
+    ShadowRays,
+    /// Every ray traced through the scene, of any kind.
+    Rays,
+    /// Ray against primitive tests.
+    IntersectionTests,
+    BvhNodeVisits,
+    /// Camera samples and the sum of the number of surfaces their paths hit.
+    Paths,
+    PathVertices,
+}
+
+const COUNTER_COUNT: usize = 7;
+
+type Counters = [AtomicU64; COUNTER_COUNT];
+
+static THREADS: Mutex<Vec<Arc<Counters>>> = Mutex::new(Vec::new());
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static COUNTERS: Arc<Counters> = {
+        let counters = Arc::new(Default::default());
+        THREADS.lock().unwrap().push(Arc::clone(&counters));
+        counters
+    };
+    /// Deepest vertex of the path of the current camera sample.
+    static PATH_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[inline]
+pub fn count(counter: Counter, n: u64) {
+    COUNTERS.with(|counters| {
+        let slot = &counters[counter as usize];
+        slot.store(slot.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+    });
+}
+
+/// Notes that the path of the current camera sample reached `depth` surfaces.
+pub fn record_depth(depth: usize) {
+    PATH_DEPTH.with(|d| d.set(d.get().max(depth)));
+}
+
+/// Ends the path of the current camera sample.
+pub fn end_path() {
+    let depth = PATH_DEPTH.with(|d| d.replace(0));
+    count(Counter::Paths, 1);
+    count(Counter::PathVertices, depth as u64);
+}
+
+/// Runs `f`, adding the time it takes to `phase`.
+pub fn time_phase<T, F: FnOnce() -> T>(phase: &'static str, f: F) -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, time)) => *time += elapsed,
+        None => phases.push((phase, elapsed)),
+    }
+    result
+}
+
+/// Totals of the counters and phases at some instant.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    counters: [u64; COUNTER_COUNT],
+    phases: Vec<(&'static str, Duration)>,
+    instant: Instant,
+}
+
+pub fn snapshot() -> Snapshot {
+    let mut counters = [0; COUNTER_COUNT];
+    for thread in THREADS.lock().unwrap().iter() {
+        for (total, slot) in counters.iter_mut().zip(thread.iter()) {
+            *total += slot.load(Ordering::Relaxed);
+        }
+    }
+    Snapshot {
+        counters,
+        phases: PHASES.lock().unwrap().clone(),
+        instant: Instant::now(),
+    }
+}
+
+impl Snapshot {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    /// Every counter, in the order of `Counter`.
+    pub fn counters(&self) -> &[u64] {
+        &self.counters
+    }
+}
+
+/// Adds counters taken elsewhere, by the workers of a distributed render, to the ones of the
+/// calling thread.
+pub fn add_all(counters: &[u64]) {
+    COUNTERS.with(|slots| {
+        for (slot, n) in slots.iter().zip(counters) {
+            slot.store(slot.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Work done between two snapshots.
+#[derive(Debug, Clone)]
+pub struct Report {
+    counters: [u64; COUNTER_COUNT],
+    phases: Vec<(&'static str, Duration)>,
+    elapsed: Duration,
+}
+
+impl Report {
+    pub fn since(start: &Snapshot) -> Report {
+        let end = snapshot();
+        let mut counters = [0; COUNTER_COUNT];
+        for (k, total) in counters.iter_mut().enumerate() {
+            *total = end.counters[k] - start.counters[k];
+        }
+        let phases = end
+            .phases
+            .iter()
+            .map(|&(name, time)| {
+                let before = start.phases.iter().find(|(n, _)| *n == name);
+                (name, time - before.map_or(Duration::ZERO, |(_, t)| *t))
+            })
+            .filter(|(_, time)| !time.is_zero())
+            .collect();
+        Report {
+            counters,
+            phases,
+            elapsed: end.instant - start.instant,
+        }
+    }
+
+    fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    /// Rays which are neither primary nor shadow rays: reflections, refractions, bounces,
+    /// photons and the like.
+    fn secondary_rays(&self) -> u64 {
+        self.get(Counter::Rays)
+            .saturating_sub(self.get(Counter::PrimaryRays) + self.get(Counter::ShadowRays))
+    }
+
+    /// Rays over the time spent tracing them, rendering and emitting photons.
+    fn rays_per_second(&self) -> f64 {
+        let tracing: Duration = self
+            .phases
+            .iter()
+            .filter(|(name, _)| *name == "render" || *name == "photons")
+            .map(|(_, time)| *time)
+            .sum();
+        self.get(Counter::Rays) as f64 / tracing.as_secs_f64().max(1e-9)
+    }
+
+    fn average_path_depth(&self) -> f64 {
+        self.get(Counter::PathVertices) as f64 / self.get(Counter::Paths).max(1) as f64
+    }
+
+    pub fn to_json(&self) -> String {
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, time)| format!("\"{}\": {:.6}", name, time.as_secs_f64()))
+            .collect();
+        format!(
+            concat!(
+                "{{\n",
+                "  \"primary_rays\": {},\n",
+                "  \"secondary_rays\": {},\n",
+                "  \"shadow_rays\": {},\n",
+                "  \"rays_per_second\": {:.1},\n",
+                "  \"intersection_tests\": {},\n",
+                "  \"bvh_node_visits\": {},\n",
+                "  \"average_path_depth\": {:.4},\n",
+                "  \"elapsed_seconds\": {:.6},\n",
+                "  \"phase_seconds\": {{{}}}\n",
+                "}}\n"
+            ),
+            self.get(Counter::PrimaryRays),
+            self.secondary_rays(),
+            self.get(Counter::ShadowRays),
+            self.rays_per_second(),
+            self.get(Counter::IntersectionTests),
+            self.get(Counter::BvhNodeVisits),
+            self.average_path_depth(),
+            self.elapsed.as_secs_f64(),
+            phases.join(", ")
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Statistics:")?;
+        let rows = [
+            ("Primary rays", self.get(Counter::PrimaryRays)),
+            ("Secondary rays", self.secondary_rays()),
+            ("Shadow rays", self.get(Counter::ShadowRays)),
+            ("Intersection tests", self.get(Counter::IntersectionTests)),
+            ("BVH node visits", self.get(Counter::BvhNodeVisits)),
+        ];
+        for (name, value) in &rows {
+            writeln!(f, "  {:<20}{:>16}", name, value)?;
+        }
+        writeln!(
+            f,
+            "  {:<20}{:>16.0}",
+            "Rays per second",
+            self.rays_per_second()
+        )?;
+        writeln!(
+            f,
+            "  {:<20}{:>16.2}",
+            "Average path depth",
+            self.average_path_depth()
+        )?;
+        writeln!(f, "  Time per phase:")?;
+        for (name, time) in &self.phases {
+            writeln!(f, "    {:<18}{:>13} ms", name, time.as_millis())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Splitting of the image into square tiles, the units of work of the renderer, and the
+//! orders in which they are handed out.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top left.
+    Scanline,
+    /// Square spiral starting at the center of the image.
+    Spiral,
+    /// Hilbert curve over the enclosing power of two square, consecutive tiles are mostly
+    /// neighbors, with jumps where the curve leaves the image.
+    Hilbert,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+/// Rectangle of pixels, the tiles on the right and bottom edges may be cut short.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// Indices of the pixels of the tile in an image `image_width` wide, row by row.
+    pub fn pixels(&self, image_width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| y * image_width + x))
+    }
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Tiles of `size` pixels covering a `width` by `height` image, in `order`.
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+    let tile = |(column, row): (usize, usize)| Tile {
+        x: column * size,
+        y: row * size,
+        width: size.min(width - column * size),
+        height: size.min(height - row * size),
+    };
+    let cells: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            (0..n * n)
+                .map(|d| hilbert(n, d))
+                .filter(|&(column, row)| column < columns && row < rows)
+                .collect()
+        }
+    };
+    cells.into_iter().map(tile).collect()
+}
+
+/// Cells of a `columns` by `rows` grid along a square spiral around its center: right, down,
+/// left and up with runs growing by one every two turns.
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let count = columns * rows;
+    let mut cells = Vec::with_capacity(count);
+    let (mut x, mut y) = (((columns as isize) - 1) / 2, ((rows as isize) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut run = 1;
+    let mut turn = 0;
+    while cells.len() < count {
+        for _ in 0..2 {
+            let (dx, dy) = directions[turn % 4];
+            for _ in 0..run {
+                if x >= 0 && y >= 0 && (x as usize) < columns && (y as usize) < rows {
+                    cells.push((x as usize, y as usize));
+                }
+                x += dx;
+                y += dy;
+            }
+            turn += 1;
+        }
+        run += 1;
+    }
+    cells
+}
+
+/// Cell at distance `d` along the Hilbert curve filling an `n` by `n` grid, `n` a power of
+/// two.
+// https://en.wikipedia.org/wiki/Hilbert_curve#Applications_and_mapping_algorithms
+fn hilbert(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
@@ -15,21 +15,21 @@ impl Add<Vec3f32> for Vec3f32 {
         Vec3f32::new(self.x + other.x, self.y + other.y, self.z + other.z)
     }
 }
-impl<'a> Add<Vec3f32> for &'a Vec3f32 {
+impl Add<Vec3f32> for &Vec3f32 {
     type Output = Vec3f32; // https://stackoverflow.com/questions/39115363/why-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x + other.x, self.y + other.y, self.z + other.z)
     }
 }
-impl<'a> Add<&'a Vec3f32> for Vec3f32 {
+impl Add<&Vec3f32> for Vec3f32 {
     type Output = Vec3f32; // https://stackoverflow.com/questions/39115363/why-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: &Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x + other.x, self.y + other.y, self.z + other.z)
     }
 }
-impl<'a> Add<&'a Vec3f32> for &'a Vec3f32 {
+impl Add<&Vec3f32> for &Vec3f32 {
     type Output = Vec3f32; // https://stackoverflow.com/questions/39115363/why-do-rusts-operators-have-the-type-output-variable
     #[inline(always)]
     fn add(self, other: &Vec3f32) -> Vec3f32 {
@@ -44,21 +44,21 @@ impl Add<f32> for Vec3f32 {
         Vec3f32::new(self.x + coef, self.y + coef, self.z + coef)
     }
 }
-impl<'a> Add<f32> for &'a Vec3f32 {
+impl Add<f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn add(self, coef: f32) -> Vec3f32 {
         Vec3f32::new(self.x + coef, self.y + coef, self.z + coef)
     }
 }
-impl<'a> Add<&'a f32> for Vec3f32 {
+impl Add<&f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn add(self, coef: &f32) -> Vec3f32 {
         Vec3f32::new(self.x + *coef, self.y + *coef, self.z + *coef)
     }
 }
-impl<'a> Add<&'a f32> for &'a Vec3f32 {
+impl Add<&f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn add(self, coef: &f32) -> Vec3f32 {
@@ -73,21 +73,21 @@ impl Sub<Vec3f32> for Vec3f32 {
         Vec3f32::new(self.x - other.x, self.y - other.y, self.z - other.z)
     }
 }
-impl<'a> Sub<Vec3f32> for &'a Vec3f32 {
+impl Sub<Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, other: Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x - other.x, self.y - other.y, self.z - other.z)
     }
 }
-impl<'a> Sub<&'a Vec3f32> for Vec3f32 {
+impl Sub<&Vec3f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, other: &Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x - other.x, self.y - other.y, self.z - other.z)
     }
 }
-impl<'a> Sub<&'a Vec3f32> for &'a Vec3f32 {
+impl Sub<&Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, other: &Vec3f32) -> Vec3f32 {
@@ -102,21 +102,21 @@ impl Sub<f32> for Vec3f32 {
         Vec3f32::new(self.x - coef, self.y - coef, self.z - coef)
     }
 }
-impl<'a> Sub<f32> for &'a Vec3f32 {
+impl Sub<f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, coef: f32) -> Vec3f32 {
         Vec3f32::new(self.x - coef, self.y - coef, self.z - coef)
     }
 }
-impl<'a> Sub<&'a f32> for Vec3f32 {
+impl Sub<&f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, coef: &f32) -> Vec3f32 {
         Vec3f32::new(self.x - *coef, self.y - *coef, self.z - *coef)
     }
 }
-impl<'a> Sub<&'a f32> for &'a Vec3f32 {
+impl Sub<&f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn sub(self, coef: &f32) -> Vec3f32 {
@@ -131,21 +131,21 @@ impl Mul<Vec3f32> for Vec3f32 {
         Vec3f32::new(self.x * other.x, self.y * other.y, self.z * other.z)
     }
 }
-impl<'a> Mul<Vec3f32> for &'a Vec3f32 {
+impl Mul<Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, other: Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x * other.x, self.y * other.y, self.z * other.z)
     }
 }
-impl<'a> Mul<&'a Vec3f32> for Vec3f32 {
+impl Mul<&Vec3f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, other: &Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x * other.x, self.y * other.y, self.z * other.z)
     }
 }
-impl<'a> Mul<&'a Vec3f32> for &'a Vec3f32 {
+impl Mul<&Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, other: &Vec3f32) -> Vec3f32 {
@@ -160,21 +160,21 @@ impl Mul<f32> for Vec3f32 {
         Vec3f32::new(self.x * coef, self.y * coef, self.z * coef)
     }
 }
-impl<'a> Mul<f32> for &'a Vec3f32 {
+impl Mul<f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, coef: f32) -> Vec3f32 {
         Vec3f32::new(self.x * coef, self.y * coef, self.z * coef)
     }
 }
-impl<'a> Mul<&'a f32> for Vec3f32 {
+impl Mul<&f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, coef: &f32) -> Vec3f32 {
         Vec3f32::new(self.x * *coef, self.y * *coef, self.z * *coef)
     }
 }
-impl<'a> Mul<&'a f32> for &'a Vec3f32 {
+impl Mul<&f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn mul(self, coef: &f32) -> Vec3f32 {
@@ -189,21 +189,21 @@ impl Div<Vec3f32> for Vec3f32 {
         Vec3f32::new(self.x / other.x, self.y / other.y, self.z / other.z)
     }
 }
-impl<'a> Div<Vec3f32> for &'a Vec3f32 {
+impl Div<Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, other: Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x / other.x, self.y / other.y, self.z / other.z)
     }
 }
-impl<'a> Div<&'a Vec3f32> for Vec3f32 {
+impl Div<&Vec3f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, other: &Vec3f32) -> Vec3f32 {
         Vec3f32::new(self.x / other.x, self.y / other.y, self.z / other.z)
     }
 }
-impl<'a> Div<&'a Vec3f32> for &'a Vec3f32 {
+impl Div<&Vec3f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, other: &Vec3f32) -> Vec3f32 {
@@ -218,21 +218,21 @@ impl Div<f32> for Vec3f32 {
         Vec3f32::new(self.x / coef, self.y / coef, self.z / coef)
     }
 }
-impl<'a> Div<f32> for &'a Vec3f32 {
+impl Div<f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, coef: f32) -> Vec3f32 {
         Vec3f32::new(self.x / coef, self.y / coef, self.z / coef)
     }
 }
-impl<'a> Div<&'a f32> for Vec3f32 {
+impl Div<&f32> for Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, coef: &f32) -> Vec3f32 {
         Vec3f32::new(self.x / *coef, self.y / *coef, self.z / *coef)
     }
 }
-impl<'a> Div<&'a f32> for &'a Vec3f32 {
+impl Div<&f32> for &Vec3f32 {
     type Output = Vec3f32;
     #[inline(always)]
     fn div(self, coef: &f32) -> Vec3f32 {
@@ -247,12 +247,12 @@ impl Vec3f32 {
     }
 
     #[inline(always)]
-    pub fn norm(self: &Self) -> f32 {
+    pub fn norm(&self) -> f32 {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
     }
 
     #[inline(always)]
-    pub fn normalize(self: &mut Self) {
+    pub fn normalize(&mut self) {
         let length = ((self.x * self.x) + (self.y * self.y) + (self.z * self.z)).sqrt();
         self.x /= length;
         self.y /= length;
@@ -260,7 +260,7 @@ impl Vec3f32 {
     }
 
     #[inline(always)] // NOTE: compiler seem to not inline method as much as free function
-    pub fn dot_product(self: &Self, other: &Vec3f32) -> f32 {
+    pub fn dot_product(&self, other: &Vec3f32) -> f32 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 }
@@ -0,0 +1,141 @@
+//! Watch mode for look development: the scene file and the files it loads are polled, every
+//! change restarts a low resolution render of the scene. The image is written whenever its
+//! samples per pixel have doubled, the samples adding up until the ones of the options. Errors
+//! in the scene are reported and the next change is awaited.
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::animation::Animation;
+use crate::color::Rgb;
+use crate::preview::Preview;
+use crate::progress::Progress;
+use crate::scene::Scene;
+use crate::scene_file::load_scene;
+use crate::tile::Tile;
+use crate::{
+    apply_options, pass_count, render_image_rounds, round_count, setup_frame, write_outputs,
+    RayTracerError, RenderOptions, ResultRayTracer,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Modification times of `paths`, `None` for the missing ones.
+fn stamps(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Waits until one of `paths` is modified, created or removed since `before` was taken.
+fn wait_for_change(paths: &[PathBuf], before: &[Option<SystemTime>]) {
+    while stamps(paths) == before {
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Options of the low resolution render of the watch mode.
+fn low_resolution(options: &RenderOptions) -> RenderOptions {
+    let scale = options.watch_scale;
+    let mut low = options.clone();
+    low.width = ((options.width as f32 * scale).round() as usize).max(1);
+    low.height = ((options.height as f32 * scale).round() as usize).max(1);
+    low.autofocus = options.autofocus.map(|(x, y)| (x * scale, y * scale));
+    low.doubling_rounds = true;
+    low
+}
+
+fn load_watched(
+    path: &Path,
+    options: &mut RenderOptions,
+) -> Result<(Scene, Animation, Vec<PathBuf>), RayTracerError> {
+    let file = load_scene(path, &mut options.camera)?;
+    let mut scene = file.scene;
+    apply_options(options, &mut scene)?;
+    Ok((scene, file.animation, file.assets))
+}
+
+/// Renders the scene of the options again whenever it changes, until killed.
+pub fn watch(options: &RenderOptions) -> ResultRayTracer {
+    let path = PathBuf::from(options.scene.as_deref().unwrap_or_default());
+    let mut watched = vec![path.clone()];
+    loop {
+        let scene_stamp = stamps(&watched[..1])[0];
+        let mut low = low_resolution(options);
+        let loaded = load_watched(&path, &mut low);
+        if let Ok((_, _, ref assets)) = loaded {
+            watched = Some(path.clone())
+                .into_iter()
+                .chain(assets.clone())
+                .collect();
+        }
+        let mut before = stamps(&watched);
+        // A change made while loading still counts.
+        before[0] = scene_stamp;
+        let (mut scene, animation, _) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                println!("{}", err);
+                println!("Waiting for changes");
+                wait_for_change(&watched, &before);
+                continue;
+            }
+        };
+        let mut low = setup_frame(&low, 0, &mut scene, &animation);
+        let cancel = Arc::new(AtomicBool::new(false));
+        low.cancel = Some(Arc::clone(&cancel));
+        let preview = low.preview.then(|| Preview::new(low.width, low.height));
+        let passes = pass_count(&low);
+        let progress = Progress::new(
+            "Rendering",
+            low.width * low.height * (passes * round_count(&low)) as usize,
+            low.progress && io::stderr().is_terminal(),
+        );
+        let on_tile = |tile: &Tile, colors: &[Rgb]| {
+            progress.advance(tile.width * tile.height);
+            if let Some(ref preview) = preview {
+                preview.update(tile, colors);
+            }
+        };
+        let low = &low;
+        let mut on_round = |pass: u32, samples: u32, rendered| {
+            write_outputs(low, rendered)?;
+            match passes {
+                1 => println!("{} at {} spp", low.output, samples),
+                _ => println!("{} at {} spp, pass {}", low.output, samples, pass + 1),
+            }
+            Ok(())
+        };
+        thread::scope(|s| {
+            s.spawn(|| {
+                wait_for_change(&watched, &before);
+                cancel.store(true, Ordering::Relaxed);
+            });
+            let rendered = render_image_rounds(
+                &scene,
+                low,
+                &low.camera,
+                None,
+                &on_tile,
+                Some(&mut on_round),
+            )
+            .and_then(|rendered| write_outputs(low, rendered));
+            progress.finish();
+            if let Some(ref preview) = preview {
+                preview.finish();
+            }
+            match rendered {
+                Ok(()) => println!("{} at {} spp", low.output, low.max_samples),
+                Err(RayTracerError::Cancelled) => (),
+                Err(err) => println!("{}", err),
+            }
+        });
+        println!("Change detected, reloading");
+    }
+}
@@ -0,0 +1,39 @@
+//! Adaptive sampling test: with a threshold, the flat sky at the top of the default scene stops
+//! at the minimum sample count while the noisy floor takes more samples.
+
+use std::path::PathBuf;
+
+use ray_tracer::float_image::read_float_image;
+
+#[test]
+fn threshold_takes_fewer_samples_in_flat_regions() {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let heatmap = directory.join("adaptive_heatmap.pfm");
+    let args: Vec<String> = format!(
+        "--width 64 --height 48 --spp 32 --threshold 0.05 -o {} --heatmap {}",
+        directory.join("adaptive.pfm").display(),
+        heatmap.display()
+    )
+    .split_whitespace()
+    .map(String::from)
+    .collect();
+    ray_tracer::run(&args).unwrap();
+
+    let image = read_float_image(&heatmap).unwrap();
+    let (width, height) = (image.width, image.height);
+    let pixels = image.layer("").unwrap();
+    // The false color ramp is pure blue up to an eighth of the maximum count, the minimum of 4
+    // samples here, and gains green and red above it.
+    let more_than_minimum = |rows: std::ops::Range<usize>| {
+        let region = &pixels[rows.start * width..rows.end * width];
+        region.iter().filter(|p| p.r > 0. || p.g > 0.).count() as f32 / region.len() as f32
+    };
+    let sky = more_than_minimum(0..height / 4);
+    let floor = more_than_minimum(height / 2..height);
+    assert!(
+        sky < 0.05 && floor > 0.5,
+        "pixels above the minimum sample count: sky {}, floor {}",
+        sky,
+        floor
+    );
+}
@@ -0,0 +1,156 @@
+//! Distributed rendering tests: a coordinator and its workers run as threads connected over
+//! localhost, the image they make must be the one rendered locally.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use ray_tracer::float_image::read_float_image;
+
+/// Address of a free localhost port, the coordinator binds it again right after.
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn read_u32(input: &mut impl Read) -> u32 {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes).unwrap();
+    u32::from_le_bytes(bytes)
+}
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+/// Renders `line` with a coordinator and `workers` workers, then checks the image against
+/// the local render of the same arguments. `before_workers` gets the address first.
+fn check_distributed(name: &str, line: &str, workers: usize, before_workers: impl FnOnce(&str)) {
+    let address = free_address();
+    let output =
+        PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("distributed_{}.pfm", name));
+    let mut coordinator_args = args(line);
+    coordinator_args.extend(args(&format!(
+        "--listen {} -o {}",
+        address,
+        output.display()
+    )));
+
+    let coordinator = thread::spawn(move || ray_tracer::run(&coordinator_args));
+    before_workers(&address);
+    let workers: Vec<_> = (0..workers)
+        .map(|_| {
+            let worker_args = args(&format!("worker {}", address));
+            thread::spawn(move || ray_tracer::run(&worker_args))
+        })
+        .collect();
+    coordinator.join().unwrap().unwrap();
+    for worker in workers {
+        worker.join().unwrap().unwrap();
+    }
+
+    let (width, height, local) = ray_tracer::render_args(&args(line)).unwrap();
+    let distributed = read_float_image(&output).unwrap();
+    assert_eq!(
+        (distributed.width, distributed.height),
+        (width, height),
+        "{}: size",
+        name
+    );
+    let distributed = distributed.layer("").unwrap();
+    let same = local
+        .iter()
+        .zip(&distributed)
+        .all(|(a, b)| (a.r, a.g, a.b) == (b.r, b.g, b.b));
+    assert!(
+        same,
+        "{}: the distributed image differs from the local one",
+        name
+    );
+}
+
+#[test]
+fn workers_match_local_render() {
+    check_distributed("whitted", "--width 64 --height 48 --spp 4", 2, |_| ());
+    check_distributed(
+        "bdpt",
+        "--width 64 --height 48 --spp 4 --integrator bdpt",
+        2,
+        |_| (),
+    );
+    check_distributed(
+        "photon",
+        "--width 64 --height 48 --spp 2 --integrator photon --photons 50000 --progressive 2",
+        2,
+        |_| (),
+    );
+}
+
+/// Connects a worker speaking the protocol of src/distributed.rs by hand, which reads the job,
+/// announces one thread and returns the first work message: the pass and the tile fields.
+fn fake_worker(address: &str) -> (TcpStream, u32, [u32; 4]) {
+    let mut stream = loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => break stream,
+            Err(_) => thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    let mut tag = [0];
+    // Job: tag 0, version, then the arguments as length prefixed strings.
+    stream.read_exact(&mut tag).unwrap();
+    assert_eq!(tag[0], 0, "job message");
+    read_u32(&mut stream);
+    for _ in 0..read_u32(&mut stream) {
+        let mut arg = vec![0; read_u32(&mut stream) as usize];
+        stream.read_exact(&mut arg).unwrap();
+    }
+    // Ready with one thread, the coordinator answers with work messages.
+    stream.write_all(&[1, 1, 0, 0, 0]).unwrap();
+    stream.read_exact(&mut tag).unwrap();
+    assert_eq!(tag[0], 2, "work message");
+    let pass = read_u32(&mut stream);
+    let tile = [0; 4].map(|_| read_u32(&mut stream));
+    (stream, pass, tile)
+}
+
+#[test]
+fn tiles_of_a_dropped_worker_are_rendered_again() {
+    // The worker disconnects before sending any result.
+    check_distributed(
+        "dropped",
+        "--width 64 --height 48 --spp 4 --tile-size 8",
+        1,
+        |address| drop(fake_worker(address)),
+    );
+}
+
+#[test]
+fn malformed_results_are_rendered_again() {
+    // A result of the right size splatting outside of the image.
+    check_distributed(
+        "malformed",
+        "--width 64 --height 48 --spp 4 --tile-size 8",
+        1,
+        |address| {
+            let (mut stream, pass, tile) = fake_worker(address);
+            let pixels = tile[2] * tile[3];
+            let mut message = vec![3];
+            let mut push = |v: u32| message.extend_from_slice(&v.to_le_bytes());
+            push(pass);
+            tile.iter().for_each(|&v| push(v));
+            push(pixels);
+            (0..pixels * 3).for_each(|_| push(0));
+            (0..pixels).for_each(|_| push(1));
+            // No AOVs, then one splat.
+            push(0);
+            push(1);
+            push(u32::MAX);
+            (0..6).for_each(|_| push(0));
+            stream.write_all(&message).unwrap();
+            // Waits for the coordinator to drop the connection.
+            let _ = stream.read_to_end(&mut Vec::new());
+        },
+    );
+}
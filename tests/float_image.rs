@@ -0,0 +1,46 @@
+//! EXR decoding test. `tests/exr/zip.exr` (ZIP, 16 scanlines per chunk) and `tests/exr/zips.exr`
+//! (ZIPS, one scanline per chunk) were written by the `exr` crate 1.74 with the 32 bit float
+//! pixels of `expected`.
+
+use std::path::Path;
+
+use ray_tracer::float_image::read_float_image;
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 12;
+
+fn expected(x: usize, y: usize) -> [f32; 3] {
+    [
+        x as f32 * 0.25 + y as f32 * 0.125,
+        (x * y) as f32 / 7.,
+        1. / (1 + x + 3 * y) as f32,
+    ]
+}
+
+#[test]
+fn zip_compressed_exr_files_decode_exactly() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/exr");
+    for name in ["zip.exr", "zips.exr"] {
+        let image = read_float_image(&directory.join(name)).unwrap();
+        assert_eq!(
+            (image.width, image.height),
+            (WIDTH, HEIGHT),
+            "{}: size",
+            name
+        );
+        let pixels = image.layer("").unwrap();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let p = pixels[y * WIDTH + x];
+                assert_eq!(
+                    [p.r, p.g, p.b],
+                    expected(x, y),
+                    "{}: pixel {} {}",
+                    name,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
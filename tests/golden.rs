@@ -0,0 +1,92 @@
+//! Golden image tests: small renders of reference scenes compared with the images committed
+//! in `tests/golden`. A failing case writes its image and a heatmap of the differences to the
+//! temporary directory of the target.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ray_tracer::float_image::{read_float_image, write_pfm};
+use ray_tracer::metrics::{compare, Metric};
+
+struct Case {
+    name: String,
+    metric: Metric,
+    tolerance: f64,
+    args: Vec<String>,
+}
+
+fn cases(path: &Path) -> Vec<Case> {
+    let text = fs::read_to_string(path).unwrap();
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace().map(String::from);
+            let mut field = || fields.next().expect("name metric tolerance args...");
+            let name = field();
+            let metric = Metric::from_name(&field()).expect("unknown metric");
+            let tolerance = field().parse().expect("tolerance is a number");
+            Case {
+                name,
+                metric,
+                tolerance,
+                args: fields.collect(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn golden_images() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let output = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&output).unwrap();
+
+    let mut failures = Vec::new();
+    for case in cases(&directory.join("cases.txt")) {
+        let (width, height, pixels) = ray_tracer::render_args(&case.args).unwrap();
+        let reference_path = directory.join(format!("{}.pfm", case.name));
+        if update {
+            write_pfm(reference_path.to_str().unwrap(), width, height, &pixels).unwrap();
+            continue;
+        }
+        let reference = read_float_image(&reference_path).unwrap();
+        assert_eq!(
+            (reference.width, reference.height),
+            (width, height),
+            "{}: size",
+            case.name
+        );
+        let reference = reference.layer("").unwrap();
+        let comparison = compare(case.metric, &reference, &pixels, width, height);
+        println!("{}: {:?} {}", case.name, case.metric, comparison.value);
+        if !case.metric.within(comparison.value, case.tolerance) {
+            let actual = output.join(format!("{}.pfm", case.name));
+            let diff = output.join(format!("{}_diff.pfm", case.name));
+            write_pfm(actual.to_str().unwrap(), width, height, &pixels).unwrap();
+            let heatmap = comparison.heatmap();
+            write_pfm(diff.to_str().unwrap(), width, height, &heatmap).unwrap();
+            failures.push(format!(
+                "{}: {:?} {} outside tolerance {}, see {}",
+                case.name,
+                case.metric,
+                comparison.value,
+                case.tolerance,
+                diff.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn image_compared_with_itself() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/whitted.pfm");
+    let image = read_float_image(&path).unwrap();
+    let pixels = image.layer("").unwrap();
+    let (width, height) = (image.width, image.height);
+    let ssim = compare(Metric::Ssim, &pixels, &pixels, width, height).value;
+    let flip = compare(Metric::Flip, &pixels, &pixels, width, height).value;
+    assert_eq!((ssim, flip), (1., 0.));
+}